@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries     INT         NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+
+CREATE TABLE issue_delivery_dead_letters
+(
+    newsletter_issue_id uuid        NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email    TEXT        NOT NULL,
+    n_retries           INT         NOT NULL,
+    last_error          TEXT        NOT NULL,
+    failed_at           timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
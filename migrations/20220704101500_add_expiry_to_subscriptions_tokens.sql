@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE subscriptions_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '24 hours';
@@ -0,0 +1,3 @@
+-- Bumped every time a user's password changes, sessions carrying an older
+-- generation are no longer accepted.
+ALTER TABLE users ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0;
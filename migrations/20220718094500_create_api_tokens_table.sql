@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE api_tokens
+(
+    token_id     uuid PRIMARY KEY,
+    user_id      uuid        NOT NULL REFERENCES users (user_id),
+    name         TEXT        NOT NULL,
+    -- Only the SHA-256 digest of the token is stored, the token itself is shown once.
+    token_hash   TEXT        NOT NULL UNIQUE,
+    scope        TEXT        NOT NULL,
+    created_at   timestamptz NOT NULL,
+    last_used_at timestamptz NULL,
+    revoked_at   timestamptz NULL
+);
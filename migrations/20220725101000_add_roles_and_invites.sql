@@ -0,0 +1,19 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN role        TEXT        NOT NULL DEFAULT 'owner'
+        CHECK (role IN ('owner', 'editor', 'viewer')),
+    ADD COLUMN email       TEXT        NULL UNIQUE,
+    ADD COLUMN disabled_at timestamptz NULL;
+-- Existing accounts (i.e. the seeded admin) become owners, new ones must pick a role.
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+
+CREATE TABLE user_invites
+(
+    invite_id   uuid PRIMARY KEY,
+    email       TEXT        NOT NULL,
+    role        TEXT        NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    invited_by  uuid        NOT NULL REFERENCES users (user_id),
+    created_at  timestamptz NOT NULL,
+    expires_at  timestamptz NOT NULL,
+    accepted_at timestamptz NULL
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+ALTER TABLE users
+    -- AES-256-GCM encrypted TOTP secret, present while enrolling or once enabled.
+    ADD COLUMN totp_secret_encrypted BYTEA       NULL,
+    ADD COLUMN totp_enabled_at       timestamptz NULL,
+    -- The last time step a code was accepted for, codes cannot be replayed.
+    ADD COLUMN totp_last_used_step   BIGINT      NULL;
+
+CREATE TABLE user_recovery_codes
+(
+    user_id   uuid        NOT NULL REFERENCES users (user_id),
+    code_hash TEXT        NOT NULL,
+    used_at   timestamptz NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE auth_audit_log
+(
+    id          uuid        NOT NULL PRIMARY KEY,
+    event       TEXT        NOT NULL,
+    username    TEXT        NULL,
+    ip_address  TEXT        NULL,
+    details     TEXT        NOT NULL,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX auth_audit_log_occurred_at_idx ON auth_audit_log (occurred_at);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE password_resets
+(
+    reset_id   uuid PRIMARY KEY,
+    user_id    uuid        NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at    timestamptz NULL
+);
+
+CREATE INDEX password_resets_user_id_idx ON password_resets (user_id);
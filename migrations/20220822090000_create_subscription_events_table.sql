@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE subscription_events
+(
+    id              uuid        NOT NULL PRIMARY KEY,
+    subscription_id uuid        NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    event           TEXT        NOT NULL,
+    actor_user_id   uuid        NULL REFERENCES users (user_id) ON DELETE SET NULL,
+    occurred_at     timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX subscription_events_subscription_id_idx ON subscription_events (subscription_id, occurred_at);
+
+-- Keyset pagination of the admin subscriber list.
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
@@ -0,0 +1,23 @@
+-- Add migration script here
+ALTER TABLE subscriptions_tokens
+    DROP CONSTRAINT subscriptions_tokens_subscription_id_fkey,
+    ADD CONSTRAINT subscriptions_tokens_subscription_id_fkey
+        FOREIGN KEY (subscription_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- A subscriber asking, by email, for a copy of their data or for its erasure.
+CREATE TABLE subscriber_data_requests
+(
+    request_id      uuid PRIMARY KEY,
+    subscription_id uuid        NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind            TEXT        NOT NULL,
+    created_at      timestamptz NOT NULL,
+    expires_at      timestamptz NOT NULL
+);
+
+-- All that is left of an erased subscriber: a keyed hash of their address, so
+-- that imports do not add them back.
+CREATE TABLE erased_subscribers
+(
+    email_hash TEXT PRIMARY KEY,
+    erased_at  timestamptz NOT NULL
+);
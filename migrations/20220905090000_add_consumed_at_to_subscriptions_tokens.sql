@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Used tokens are kept for a while so that following the link again can tell
+-- the subscriber they are already confirmed.
+ALTER TABLE subscriptions_tokens
+    ADD COLUMN consumed_at timestamptz NULL;
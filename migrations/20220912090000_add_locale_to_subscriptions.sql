@@ -0,0 +1,4 @@
+-- Add migration script here
+-- NULL means the default locale of the email templates.
+ALTER TABLE subscriptions
+    ADD COLUMN locale TEXT NULL;
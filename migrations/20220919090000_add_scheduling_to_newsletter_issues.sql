@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Issues can be written ahead of time: `scheduled` ones get their deliveries
+-- enqueued once `send_at` is due, unless they are `cancelled` before that.
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues
+    ADD COLUMN send_at timestamptz NULL;
+-- Only known once the issue actually goes out.
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at DROP NOT NULL;
+
+CREATE INDEX newsletter_issues_scheduled_idx ON newsletter_issues (send_at)
+    WHERE status = 'scheduled';
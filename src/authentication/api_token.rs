@@ -0,0 +1,207 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::auth::AuthError;
+use crate::authentication::role::Role;
+
+const TOKEN_PREFIX: &str = "z2p_";
+const TOKEN_LENGTH: usize = 40;
+
+/// What a token is allowed to do, a token only ever carries one scope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiTokenScope {
+    PublishNewsletters,
+}
+
+impl ApiTokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiTokenScope::PublishNewsletters => "newsletters:publish",
+        }
+    }
+
+    /// A token never grants more than its owner's role does.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ApiTokenScope::PublishNewsletters => Role::Editor,
+        }
+    }
+}
+
+impl TryFrom<String> for ApiTokenScope {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "newsletters:publish" => Ok(ApiTokenScope::PublishNewsletters),
+            other => anyhow::bail!("{} is not a supported API token scope.", other),
+        }
+    }
+}
+
+/// Tokens are long random strings, unlike passwords they do not need a slow
+/// hash: a plain SHA-256 digest is enough and lets us look them up directly.
+pub fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+fn generate_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
+
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Returns the plain-text token, it is not stored anywhere and cannot be recovered.
+#[tracing::instrument(name = "Create API token", skip(pool))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    scope: ApiTokenScope,
+    pool: &PgPool,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scope, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(&token),
+        scope.as_str(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new API token.")?;
+    Ok(token)
+}
+
+/// Returns `false` if the token does not exist, is not owned by `user_id` or was
+/// already revoked.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    token_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE
+            token_id = $1 AND
+            user_id = $2 AND
+            revoked_at IS NULL
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+#[tracing::instrument(name = "List active API tokens", skip(pool))]
+pub async fn get_active_api_tokens(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT token_id, name, scope, created_at, last_used_at
+        FROM api_tokens
+        WHERE
+            user_id = $1 AND
+            revoked_at IS NULL
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve API tokens.")?;
+    Ok(tokens)
+}
+
+/// Resolves a bearer token to the user it was issued to, as long as it is
+/// still active, carries the `required` scope and its owner's account is
+/// enabled with a role allowing it.
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+pub async fn validate_api_token(
+    token: Secret<String>,
+    required: ApiTokenScope,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens t
+        SET last_used_at = now()
+        FROM users u
+        WHERE
+            t.token_hash = $1 AND
+            t.revoked_at IS NULL AND
+            u.user_id = t.user_id AND
+            u.disabled_at IS NULL
+        RETURNING t.user_id, t.scope, u.role
+        "#,
+        hash_token(&token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown API token")))?;
+
+    let scope = ApiTokenScope::try_from(row.scope).map_err(AuthError::InvalidCredentials)?;
+    let role = Role::try_from(row.role).map_err(anyhow::Error::msg)?;
+    if scope != required || role < required.required_role() {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "The API token is not allowed to {}",
+            required.as_str()
+        )));
+    }
+    Ok(row.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::*;
+
+    #[test]
+    fn generated_tokens_are_prefixed_and_unique() {
+        let a = generate_token();
+        let b = generate_token();
+        assert!(a.expose_secret().starts_with(TOKEN_PREFIX));
+        assert_eq!(a.expose_secret().len(), TOKEN_PREFIX.len() + TOKEN_LENGTH);
+        assert_ne!(a.expose_secret(), b.expose_secret());
+    }
+
+    #[test]
+    fn hashing_is_deterministic() {
+        let token = Secret::new("z2p_token".to_string());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), "z2p_token");
+    }
+}
@@ -0,0 +1,44 @@
+use std::net::IpAddr;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Security relevant events worth keeping around after the logs have rotated.
+#[derive(Debug, Clone, Copy)]
+pub enum AuditEvent {
+    Lockout,
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::Lockout => "lockout",
+        }
+    }
+}
+
+#[tracing::instrument(name = "Record audit event", skip(pool, details))]
+pub async fn record_audit_event(
+    pool: &PgPool,
+    event: AuditEvent,
+    username: Option<&str>,
+    ip_address: Option<IpAddr>,
+    details: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO auth_audit_log (id, event, username, ip_address, details)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        event.as_str(),
+        username,
+        ip_address.map(|ip| ip.to_string()),
+        details
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record an audit event")?;
+    Ok(())
+}
@@ -1,9 +1,11 @@
 use anyhow::Context;
 use argon2::password_hash::SaltString;
-use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use argon2::{Algorithm, Argon2, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
+use crate::authentication::role::Role;
+use crate::config::PasswordHashingSettings;
 use crate::telemetry::spawn_blocking_with_tracing;
 
 #[derive(thiserror::Error, Debug)]
@@ -19,33 +21,125 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+#[tracing::instrument(name = "Validate credentials", skip(credentials, hashing, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing: &PasswordHashingSettings,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
-    let mut user_id = None;
-    // to avoid attackers figure how a user exists or not, we create a fake hash we force
-    // to ensure the same amount of work is done regardless of if they exists or not.
-    let mut expected_password_hash = Secret::new("$argon2id$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno".to_string());
-
-    if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(&credentials.username, &pool)
-            .await
-            .map_err(AuthError::UnexpectedError)?
-    {
-        user_id = Some(stored_user_id);
-        expected_password_hash = stored_password_hash;
-    }
+    let stored_credentials = get_stored_credentials(&credentials.username, pool)
+        .await
+        .map_err(AuthError::UnexpectedError)?;
+    let (user_id, expected_password_hash) = match stored_credentials {
+        Some((user_id, password_hash)) => (Some(user_id), Some(password_hash)),
+        None => (None, None),
+    };
 
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password);
+    let hashing = hashing.clone();
+    let upgrade = spawn_blocking_with_tracing(move || {
+        verify_and_upgrade_password_hash(expected_password_hash, credentials.password, &hashing)
     })
     .await
     .context("Failed to spawn blocking task.")
-    .map_err(AuthError::UnexpectedError)?;
+    .map_err(AuthError::UnexpectedError)??;
+
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))?;
+
+    // The password was right, a failure to store its new hash must not fail the login.
+    if let Some((old_password_hash, new_password_hash)) = upgrade {
+        if let Err(e) =
+            store_upgraded_password_hash(user_id, &old_password_hash, &new_password_hash, pool)
+                .await
+        {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to store an upgraded password hash"
+            );
+        }
+    }
+    Ok(user_id)
+}
+
+/// The stored password hash and the one replacing it.
+type HashUpgrade = (Secret<String>, Secret<String>);
+
+/// Returns the stored hash along with a fresh one when the stored hash was
+/// produced with a different algorithm or weaker parameters than configured.
+fn verify_and_upgrade_password_hash(
+    expected_password_hash: Option<Secret<String>>,
+    password_candidate: Secret<String>,
+    hashing: &PasswordHashingSettings,
+) -> Result<Option<HashUpgrade>, AuthError> {
+    let expected_password_hash = match expected_password_hash {
+        Some(expected_password_hash) => expected_password_hash,
+        None => {
+            // to avoid attackers figure how a user exists or not, unknown usernames
+            // pay for an Argon2 run with the current parameters as well.
+            compute_password_hash(password_candidate, hashing)?;
+            return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                "Unknown username"
+            )));
+        }
+    };
+
+    verify_password_hash(&expected_password_hash, &password_candidate)?;
 
-    user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))
+    if needs_rehash(expected_password_hash.expose_secret(), hashing)? {
+        let new_password_hash = compute_password_hash(password_candidate, hashing)?;
+        Ok(Some((expected_password_hash, new_password_hash)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Hashes produced by another algorithm or Argon2 version, or with any cost
+/// below the configured one, are due for an upgrade.
+pub fn needs_rehash(
+    password_hash: &str,
+    hashing: &PasswordHashingSettings,
+) -> Result<bool, anyhow::Error> {
+    let password_hash =
+        PasswordHash::new(password_hash).context("Failed to parse hash in PHC string format.")?;
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13 as u32)
+    {
+        return Ok(true);
+    }
+
+    let cost = |name: &str| password_hash.params.get_decimal(name).unwrap_or(0);
+    Ok(cost("m") < hashing.memory_cost_kib
+        || cost("t") < hashing.time_cost
+        || cost("p") < hashing.parallelism)
+}
+
+/// Only replaces the hash we verified against: if the password was changed in
+/// the meantime the upgrade is simply dropped.
+#[tracing::instrument(
+    name = "Store upgraded password hash",
+    skip(old_password_hash, new_password_hash, pool)
+)]
+async fn store_upgraded_password_hash(
+    user_id: uuid::Uuid,
+    old_password_hash: &Secret<String>,
+    new_password_hash: &Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        new_password_hash.expose_secret(),
+        user_id,
+        old_password_hash.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash.")?;
+    Ok(())
 }
 
 #[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
@@ -57,7 +151,9 @@ pub async fn get_stored_credentials(
         r#"
             SELECT user_id, password_hash
             FROM users
-            WHERE username = $1
+            WHERE
+                username = $1 AND
+                disabled_at IS NULL
         "#,
         username,
     )
@@ -74,8 +170,8 @@ pub async fn get_stored_credentials(
     skip(expected_password_hash, password_candidate)
 )]
 pub fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
+    expected_password_hash: &Secret<String>,
+    password_candidate: &Secret<String>,
 ) -> Result<(), AuthError> {
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format.")?;
@@ -89,38 +185,142 @@ pub fn verify_password_hash(
         .map_err(AuthError::InvalidCredentials)
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+/// Changing the password bumps the user's session generation, which logs out
+/// every session created before the change. Returns the new generation.
+#[tracing::instrument(name = "Change password", skip(password, hashing, executor))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
-    pool: &PgPool,
-) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
-        .await?
-        .context("Failed to hash password")?;
-    sqlx::query!(
+    hashing: &PasswordHashingSettings,
+    executor: impl PgExecutor<'_>,
+) -> Result<i32, anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
+    let row = sqlx::query!(
         r#"
         UPDATE users
-        SET password_hash = $1
+        SET
+            password_hash = $1,
+            session_generation = session_generation + 1
         WHERE user_id = $2
+        RETURNING session_generation
         "#,
         password_hash.expose_secret(),
         user_id
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await
     .context("Failed to change user's password in the database.")?;
-    Ok(())
+    Ok(row.session_generation)
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+pub struct Account {
+    pub session_generation: i32,
+    pub role: Role,
+    pub disabled: bool,
+}
+
+/// Everything we need to decide whether a logged-in session may carry on.
+#[tracing::instrument(name = "Get account", skip(pool))]
+pub async fn get_account(user_id: uuid::Uuid, pool: &PgPool) -> Result<Account, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT session_generation, role, disabled_at
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
     )
-    .hash_password(password.expose_secret().as_bytes(), &salt)?
-    .to_string();
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the user's account.")?;
+    Ok(Account {
+        session_generation: row.session_generation,
+        role: Role::try_from(row.role).map_err(anyhow::Error::msg)?,
+        disabled: row.disabled_at.is_some(),
+    })
+}
+
+#[tracing::instrument(name = "Get session generation", skip(pool))]
+pub async fn get_session_generation(
+    user_id: uuid::Uuid,
+    pool: &PgPool,
+) -> Result<i32, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT session_generation
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the user's session generation.")?;
+    Ok(row.session_generation)
+}
+
+pub(crate) fn compute_password_hash(
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, hashing.params()?)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
     Ok(Secret::new(password_hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok_eq};
+
+    use crate::authentication::auth::needs_rehash;
+    use crate::config::PasswordHashingSettings;
+
+    const HASH: &str = "gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+    fn hashing(memory_cost_kib: u32, time_cost: u32, parallelism: u32) -> PasswordHashingSettings {
+        PasswordHashingSettings {
+            memory_cost_kib,
+            time_cost,
+            parallelism,
+        }
+    }
+
+    #[test]
+    fn a_hash_with_the_configured_parameters_is_kept() {
+        let stored = format!("$argon2id$v=19$m=15000,t=2,p=1${}", HASH);
+        assert_ok_eq!(needs_rehash(&stored, &hashing(15000, 2, 1)), false);
+    }
+
+    #[test]
+    fn a_hash_with_stronger_parameters_is_kept() {
+        let stored = format!("$argon2id$v=19$m=19456,t=3,p=2${}", HASH);
+        assert_ok_eq!(needs_rehash(&stored, &hashing(15000, 2, 1)), false);
+    }
+
+    #[test]
+    fn a_hash_with_any_weaker_parameter_is_upgraded() {
+        for params in ["m=4096,t=2,p=1", "m=15000,t=1,p=1", "m=15000,t=2,p=1"] {
+            let stored = format!("$argon2id$v=19${}${}", params, HASH);
+            assert_ok_eq!(needs_rehash(&stored, &hashing(15000, 2, 2)), true);
+        }
+    }
+
+    #[test]
+    fn a_hash_from_another_algorithm_or_version_is_upgraded() {
+        for prefix in ["$argon2i$v=19", "$argon2d$v=19", "$argon2id$v=16"] {
+            let stored = format!("{}$m=15000,t=2,p=1${}", prefix, HASH);
+            assert_ok_eq!(needs_rehash(&stored, &hashing(15000, 2, 1)), true);
+        }
+    }
+
+    #[test]
+    fn an_unparsable_hash_is_an_error() {
+        assert_err!(needs_rehash("not-a-phc-string", &hashing(15000, 2, 1)));
+    }
+}
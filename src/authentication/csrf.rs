@@ -0,0 +1,126 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::ContentType;
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use actix_web_lab::middleware::Next;
+use futures_util::StreamExt;
+use rand::RngCore;
+
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+/// Name of the form field (or query parameter) carrying the token.
+pub const CSRF_FIELD_NAME: &str = "csrf_token";
+
+/// Returns the anti-forgery token of the session, generating one on first use.
+/// The token lives as long as the session does: `log_out` purges it along with
+/// everything else.
+pub fn csrf_token(session: &TypedSession) -> Result<String, serde_json::Error> {
+    if let Some(token) = session.get_csrf_token()? {
+        return Ok(token);
+    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    session.insert_csrf_token(&token)?;
+    Ok(token)
+}
+
+/// The hidden input every form posting to a protected route has to carry.
+pub fn csrf_field(session: &TypedSession) -> Result<String, serde_json::Error> {
+    Ok(format!(
+        r#"<input type="hidden" name="{}" value="{}">"#,
+        CSRF_FIELD_NAME,
+        csrf_token(session)?
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct SubmittedToken {
+    csrf_token: Option<String>,
+}
+
+/// Synchronizer token check for cookie-authenticated form posts: a POST goes
+/// through only if it carries the token stored in its session. Other methods
+/// are let through untouched.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.method() != Method::POST {
+        return next.call(req).await;
+    }
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session.get_csrf_token().map_err(e500)?;
+    let submitted = submitted_token(&mut req).await?;
+
+    match (expected, submitted) {
+        (Some(expected), Some(submitted)) if tokens_match(&expected, &submitted) => {
+            next.call(req).await
+        }
+        _ => {
+            let response = HttpResponse::Forbidden()
+                .content_type(ContentType::plaintext())
+                .body("This form has expired, please reload the page and try again.");
+            let e = anyhow::anyhow!("The request carried a missing or invalid CSRF token");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// URL-encoded forms carry the token as a field, which means buffering the body
+/// and handing it back to the request for the handler's own extractors. Other
+/// bodies (e.g. file uploads) pass it in the query string instead.
+async fn submitted_token(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return Ok(web::Query::<SubmittedToken>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().csrf_token));
+    }
+    let mut body = web::BytesMut::new();
+    let mut payload = req.take_payload();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    let body = body.freeze();
+    let submitted = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| web::Query::<SubmittedToken>::from_query(body).ok())
+        .and_then(|form| form.into_inner().csrf_token);
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    req.set_payload(payload.into());
+    Ok(submitted)
+}
+
+/// Compares in constant time so the token cannot be guessed byte by byte.
+fn tokens_match(expected: &str, submitted: &str) -> bool {
+    expected.len() == submitted.len()
+        && expected
+            .bytes()
+            .zip(submitted.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokens_match;
+
+    #[test]
+    fn identical_tokens_match() {
+        assert!(tokens_match("abc123", "abc123"));
+    }
+
+    #[test]
+    fn different_tokens_do_not_match() {
+        assert!(!tokens_match("abc123", "abc124"));
+        assert!(!tokens_match("abc123", "abc12"));
+        assert!(!tokens_match("abc123", ""));
+    }
+}
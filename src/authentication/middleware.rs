@@ -1,13 +1,18 @@
 use std::ops::Deref;
 
 use actix_web::body::MessageBody;
+use actix_web::cookie::Key;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::error::InternalError;
-use actix_web::{FromRequest, HttpMessage};
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
 use actix_web_lab::middleware::Next;
+use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::session_state::TypedSession;
+use crate::authentication::auth::get_account;
+use crate::authentication::role::Role;
+use crate::authentication::session_registry::{ClientInfo, SessionRegistry};
+use crate::session_state::{session_store_key, TypedSession, SESSION_COOKIE_NAME};
 use crate::utils::middleware::{e500, see_other};
 
 #[derive(Copy, Clone, Debug)]
@@ -38,8 +43,44 @@ pub async fn reject_anonymous_users(
 
     match session.get_user_id().map_err(e500)? {
         Some(user_id) => {
+            // Sessions created before the user's last password change, belonging
+            // to a disabled account or revoked from the sessions page are no longer valid.
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .ok_or_else(|| e500("The database pool is not registered as app data"))?;
+            let registry = req
+                .app_data::<web::Data<SessionRegistry>>()
+                .ok_or_else(|| e500("The session registry is not registered as app data"))?;
+            let cookie_key = req
+                .app_data::<web::Data<Key>>()
+                .ok_or_else(|| e500("The session cookie key is not registered as app data"))?;
+
+            let account = get_account(user_id, pool).await.map_err(e500)?;
+            let registered = match session.get_session_id().map_err(e500)? {
+                Some(session_id) => {
+                    let store_key = session_store_key(req.cookie(SESSION_COOKIE_NAME), cookie_key);
+                    let client = ClientInfo::new(req.peer_addr(), req.headers());
+                    registry
+                        .touch(user_id, session_id, client, store_key)
+                        .await
+                        .map_err(e500)?
+                }
+                None => false,
+            };
+            if account.disabled
+                || !registered
+                || session.get_session_generation().map_err(e500)?
+                    != Some(account.session_generation)
+            {
+                session.log_out();
+                let response = see_other("/login");
+                let e = anyhow::anyhow!("The session is no longer valid");
+                return Err(InternalError::from_response(e, response).into());
+            }
+
             // add user id as a request extension (basically add data to request) information to the request.
             req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(account.role);
 
             // call the next middleware
             next.call(req).await
@@ -51,3 +92,34 @@ pub async fn reject_anonymous_users(
         }
     }
 }
+
+/// Role checks are layered on top of `reject_anonymous_users`, which is the one
+/// putting the user's `Role` in the request extensions.
+async fn require_role(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+    required: Role,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= required => next.call(req).await,
+        _ => {
+            let e = anyhow::anyhow!("The user does not have the {} role", required);
+            Err(InternalError::from_response(e, HttpResponse::Forbidden().finish()).into())
+        }
+    }
+}
+
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(req, next, Role::Editor).await
+}
+
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(req, next, Role::Owner).await
+}
@@ -1,2 +1,11 @@
+pub mod api_token;
+pub mod audit;
 pub mod auth;
+pub mod csrf;
 pub mod middleware;
+pub mod password_policy;
+pub mod role;
+pub mod session_registry;
+pub mod throttle;
+pub mod totp;
+pub mod two_factor;
@@ -0,0 +1,91 @@
+use secrecy::{ExposeSecret, Secret};
+
+/// OWASP recommends at least 12 characters, the upper bound keeps the hashing
+/// cost of a single request under control.
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+// A local list of well-known breached passwords, one per line, lower-cased.
+const BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PasswordPolicyError {
+    #[error(
+        "The new password must be at least {} characters long.",
+        MIN_PASSWORD_LENGTH
+    )]
+    TooShort,
+    #[error(
+        "The new password must be at most {} characters long.",
+        MAX_PASSWORD_LENGTH
+    )]
+    TooLong,
+    #[error("The new password appears in a list of breached passwords, please pick another one.")]
+    Breached,
+}
+
+pub fn validate_new_password(password: &Secret<String>) -> Result<(), PasswordPolicyError> {
+    let password = password.expose_secret();
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(PasswordPolicyError::TooShort);
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(PasswordPolicyError::TooLong);
+    }
+    let candidate = password.to_lowercase();
+    if BREACHED_PASSWORDS
+        .lines()
+        .map(str::trim)
+        .any(|breached| breached == candidate)
+    {
+        return Err(PasswordPolicyError::Breached);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::*;
+
+    fn secret(s: &str) -> Secret<String> {
+        Secret::new(s.to_string())
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert_eq!(
+            validate_new_password(&secret("short")),
+            Err(PasswordPolicyError::TooShort)
+        );
+    }
+
+    #[test]
+    fn long_passwords_are_rejected() {
+        let password = "a".repeat(MAX_PASSWORD_LENGTH + 1);
+        assert_eq!(
+            validate_new_password(&secret(&password)),
+            Err(PasswordPolicyError::TooLong)
+        );
+    }
+
+    #[test]
+    fn length_is_counted_in_characters_not_bytes() {
+        assert_ok!(validate_new_password(&secret(
+            &"é".repeat(MAX_PASSWORD_LENGTH)
+        )));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_regardless_of_case() {
+        assert_err!(validate_new_password(&secret("Password1234")));
+    }
+
+    #[test]
+    fn a_reasonable_password_is_accepted() {
+        assert_ok!(validate_new_password(&secret("gandalf-rides-shadowfax")));
+    }
+}
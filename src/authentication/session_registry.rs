@@ -0,0 +1,216 @@
+use std::net::SocketAddr;
+
+use actix_session::storage::{RedisSessionStore, SessionKey, SessionStore};
+use actix_web::http::header::{HeaderMap, USER_AGENT};
+use anyhow::Context;
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use uuid::Uuid;
+
+/// actix-session keeps the state of a browser session for a day by default,
+/// records not seen for that long belong to sessions that are gone.
+const SESSION_RECORD_TTL_SECS: i64 = 24 * 60 * 60;
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Where a request came from, as far as we can tell.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(peer_addr: Option<SocketAddr>, headers: &HeaderMap) -> Self {
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        Self {
+            ip_address: peer_addr.map(|addr| addr.ip().to_string()),
+            user_agent,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SessionRecord {
+    pub session_id: Uuid,
+    /// Unix timestamps, in seconds.
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// The key of the session state in `RedisSessionStore`, only known once the
+    /// browser has sent its session cookie back.
+    store_key: Option<String>,
+}
+
+/// Keeps track of every logged-in session of a user in a Redis hash, keyed by
+/// the session id we put in the session state at login.
+///
+/// A session missing from the registry has been revoked: `reject_anonymous_users`
+/// logs it out the next time it shows up. Revoking also deletes the session
+/// state from `RedisSessionStore` right away whenever its key is known.
+#[derive(Clone)]
+pub struct SessionRegistry {
+    redis: ConnectionManager,
+    store: RedisSessionStore,
+    key_prefix: String,
+}
+
+impl SessionRegistry {
+    pub fn new(redis: ConnectionManager, store: RedisSessionStore, key_prefix: String) -> Self {
+        Self {
+            redis,
+            store,
+            key_prefix,
+        }
+    }
+
+    fn registry_key(&self, user_id: Uuid) -> String {
+        format!("{}:sessions:{}", self.key_prefix, user_id)
+    }
+
+    async fn save(&self, user_id: Uuid, record: &SessionRecord) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let key = self.registry_key(user_id);
+        let value =
+            serde_json::to_string(record).context("Failed to serialize a session record")?;
+        redis
+            .hset::<_, _, _, ()>(&key, record.session_id.to_string(), value)
+            .await
+            .context("Failed to store a session record in Redis")?;
+        redis
+            .expire::<_, ()>(&key, SESSION_RECORD_TTL_SECS as usize)
+            .await
+            .context("Failed to set the expiry of the session registry in Redis")?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<Option<SessionRecord>, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let value: Option<String> = redis
+            .hget(self.registry_key(user_id), session_id.to_string())
+            .await
+            .context("Failed to read a session record from Redis")?;
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .context("Failed to deserialize a session record")
+    }
+
+    #[tracing::instrument(name = "Register session", skip(self))]
+    pub async fn register(&self, user_id: Uuid, client: ClientInfo) -> Result<Uuid, anyhow::Error> {
+        let now = Utc::now().timestamp();
+        let record = SessionRecord {
+            session_id: Uuid::new_v4(),
+            created_at: now,
+            last_seen_at: now,
+            ip_address: client.ip_address,
+            user_agent: client.user_agent,
+            store_key: None,
+        };
+        self.save(user_id, &record).await?;
+        Ok(record.session_id)
+    }
+
+    /// Refreshes the record of a session that is being used. Returns `false`
+    /// when the session is not registered (anymore).
+    #[tracing::instrument(name = "Touch session", skip(self, store_key))]
+    pub async fn touch(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        client: ClientInfo,
+        store_key: Option<String>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut record = match self.get(user_id, session_id).await? {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+        record.last_seen_at = Utc::now().timestamp();
+        record.ip_address = client.ip_address;
+        record.user_agent = client.user_agent;
+        if store_key.is_some() {
+            record.store_key = store_key;
+        }
+        self.save(user_id, &record).await?;
+        Ok(true)
+    }
+
+    /// Most recently used first. Records of sessions that have expired on their
+    /// own are dropped along the way.
+    #[tracing::instrument(name = "List sessions", skip(self))]
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<SessionRecord>, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let key = self.registry_key(user_id);
+        let values: Vec<String> = redis
+            .hvals(&key)
+            .await
+            .context("Failed to read the session registry from Redis")?;
+
+        let cutoff = Utc::now().timestamp() - SESSION_RECORD_TTL_SECS;
+        let mut records = Vec::with_capacity(values.len());
+        for value in values {
+            let record: SessionRecord =
+                serde_json::from_str(&value).context("Failed to deserialize a session record")?;
+            if record.last_seen_at < cutoff {
+                redis
+                    .hdel::<_, _, ()>(&key, record.session_id.to_string())
+                    .await
+                    .context("Failed to remove an expired session record from Redis")?;
+            } else {
+                records.push(record);
+            }
+        }
+        records.sort_by_key(|record| std::cmp::Reverse(record.last_seen_at));
+        Ok(records)
+    }
+
+    #[tracing::instrument(name = "Revoke session", skip(self))]
+    pub async fn revoke(&self, user_id: Uuid, session_id: Uuid) -> Result<(), anyhow::Error> {
+        if let Some(record) = self.get(user_id, session_id).await? {
+            self.purge(user_id, record).await?;
+        }
+        Ok(())
+    }
+
+    /// Revokes every session of the user but `keep`, returns how many went.
+    #[tracing::instrument(name = "Revoke other sessions", skip(self))]
+    pub async fn revoke_all_except(
+        &self,
+        user_id: Uuid,
+        keep: Option<Uuid>,
+    ) -> Result<usize, anyhow::Error> {
+        let mut n_revoked = 0;
+        for record in self.list(user_id).await? {
+            if Some(record.session_id) != keep {
+                self.purge(user_id, record).await?;
+                n_revoked += 1;
+            }
+        }
+        Ok(n_revoked)
+    }
+
+    async fn purge(&self, user_id: Uuid, record: SessionRecord) -> Result<(), anyhow::Error> {
+        if let Some(store_key) = record.store_key {
+            let session_key = SessionKey::try_from(store_key)
+                .map_err(|e| anyhow::anyhow!("Invalid session key: {}", e))?;
+            self.store
+                .delete(&session_key)
+                .await
+                .context("Failed to delete a session from the session store")?;
+        }
+        let mut redis = self.redis.clone();
+        redis
+            .hdel::<_, _, ()>(self.registry_key(user_id), record.session_id.to_string())
+            .await
+            .context("Failed to remove a session record from Redis")?;
+        Ok(())
+    }
+}
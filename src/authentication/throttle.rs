@@ -0,0 +1,400 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::audit::{record_audit_event, AuditEvent};
+use crate::authentication::auth::{validate_credentials, AuthError, Credentials};
+use crate::config::{LoginThrottlingSettings, PasswordHashingSettings};
+
+/// Failed password checks are counted in Redis, both per username and per
+/// client IP. Every recent failure makes the next attempt wait a little longer,
+/// and once either counter reaches its limit that username or IP is locked out
+/// for a while: attempts are refused before any Argon2 work is done.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    redis: ConnectionManager,
+    key_prefix: String,
+    settings: LoginThrottlingSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockoutScope {
+    Username,
+    Ip,
+}
+
+impl LockoutScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LockoutScope::Username => "username",
+            LockoutScope::Ip => "ip",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ThrottleDecision {
+    Allowed { delay: Duration },
+    LockedOut { retry_after: Duration },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThrottledAuthError {
+    #[error("Too many failed attempts.")]
+    LockedOut { retry_after: Duration },
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+}
+
+impl LoginThrottle {
+    pub fn new(
+        redis: ConnectionManager,
+        key_prefix: String,
+        settings: LoginThrottlingSettings,
+    ) -> Self {
+        Self {
+            redis,
+            key_prefix,
+            settings,
+        }
+    }
+
+    /// The address failures are counted against. Behind a load balancer every
+    /// request comes from the balancer itself, so with `trust_proxy_headers` on
+    /// we read the client's address from `Forwarded`/`X-Forwarded-For` instead.
+    /// Only turn it on behind a proxy that sets those headers, anyone can send them.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        if !self.settings.trust_proxy_headers {
+            return request.peer_addr().map(|addr| addr.ip());
+        }
+        let connection_info = request.connection_info();
+        connection_info.realip_remote_addr().and_then(parse_ip)
+    }
+
+    fn subjects(&self, username: &str, ip: Option<IpAddr>) -> Vec<(LockoutScope, String)> {
+        // Usernames are compared case-insensitively so that changing the case
+        // does not buy an attacker a fresh counter.
+        let mut subjects = vec![(LockoutScope::Username, username.to_lowercase())];
+        if let Some(ip) = ip {
+            subjects.push((LockoutScope::Ip, ip.to_string()));
+        }
+        subjects
+    }
+
+    fn failures_key(&self, scope: LockoutScope, subject: &str) -> String {
+        format!(
+            "{}:login_failures:{}:{}",
+            self.key_prefix,
+            scope.as_str(),
+            subject
+        )
+    }
+
+    fn lockout_key(&self, scope: LockoutScope, subject: &str) -> String {
+        format!(
+            "{}:login_lockout:{}:{}",
+            self.key_prefix,
+            scope.as_str(),
+            subject
+        )
+    }
+
+    fn second_factor_failures_key(&self, user_id: Uuid) -> String {
+        format!("{}:second_factor_failures:{}", self.key_prefix, user_id)
+    }
+
+    fn max_failures(&self, scope: LockoutScope) -> u32 {
+        match scope {
+            LockoutScope::Username => self.settings.max_failures_per_username,
+            LockoutScope::Ip => self.settings.max_failures_per_ip,
+        }
+    }
+
+    #[tracing::instrument(name = "Check login throttle", skip(self))]
+    pub async fn check(
+        &self,
+        username: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<ThrottleDecision, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let mut failures = 0;
+        for (scope, subject) in self.subjects(username, ip) {
+            // -2 when the key does not exist, -1 when it has no expiry.
+            let ttl: i64 = redis
+                .ttl(self.lockout_key(scope, &subject))
+                .await
+                .context("Failed to read a lockout from Redis")?;
+            if ttl > 0 {
+                return Ok(ThrottleDecision::LockedOut {
+                    retry_after: Duration::from_secs(ttl as u64),
+                });
+            }
+
+            let count: Option<u32> = redis
+                .get(self.failures_key(scope, &subject))
+                .await
+                .context("Failed to read a failure counter from Redis")?;
+            failures = failures.max(count.unwrap_or(0));
+        }
+        Ok(ThrottleDecision::Allowed {
+            delay: progressive_delay(
+                failures,
+                self.settings.base_delay_ms,
+                self.settings.max_delay_ms,
+            ),
+        })
+    }
+
+    /// Counts a failed attempt against the username and the IP. Every lockout it
+    /// triggers is written to the audit log.
+    #[tracing::instrument(name = "Record failed login", skip(self, pool))]
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        ip: Option<IpAddr>,
+        pool: &PgPool,
+    ) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        for (scope, subject) in self.subjects(username, ip) {
+            let failures_key = self.failures_key(scope, &subject);
+            let failures: u32 = redis
+                .incr(&failures_key, 1)
+                .await
+                .context("Failed to increment a failure counter in Redis")?;
+            if failures == 1 {
+                redis
+                    .expire::<_, ()>(&failures_key, self.settings.failure_window_secs as usize)
+                    .await
+                    .context("Failed to set the expiry of a failure counter in Redis")?;
+            }
+
+            if failures >= self.max_failures(scope) {
+                redis
+                    .set_ex::<_, _, ()>(
+                        self.lockout_key(scope, &subject),
+                        failures,
+                        self.settings.lockout_secs as usize,
+                    )
+                    .await
+                    .context("Failed to store a lockout in Redis")?;
+                redis
+                    .del::<_, ()>(&failures_key)
+                    .await
+                    .context("Failed to reset a failure counter in Redis")?;
+
+                tracing::warn!(
+                    scope = scope.as_str(),
+                    subject = %subject,
+                    "Locking out after {} failed login attempts",
+                    failures
+                );
+                record_audit_event(
+                    pool,
+                    AuditEvent::Lockout,
+                    Some(username),
+                    ip,
+                    &format!(
+                        "{} locked out for {}s after {} failed attempts",
+                        scope.as_str(),
+                        self.settings.lockout_secs,
+                        failures
+                    ),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Invalid second factor codes are counted per account, apart from password
+    /// failures: knowing the password must not buy fresh guesses at the code.
+    /// Once the limit is reached the account cannot complete a second step
+    /// until the lockout expires.
+    #[tracing::instrument(name = "Check second factor throttle", skip(self))]
+    pub async fn is_second_factor_locked_out(&self, user_id: Uuid) -> Result<bool, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let failures: Option<u32> = redis
+            .get(self.second_factor_failures_key(user_id))
+            .await
+            .context("Failed to read a failure counter from Redis")?;
+        Ok(failures.unwrap_or(0) >= self.settings.max_second_factor_failures)
+    }
+
+    /// Returns whether this failure locked the account out.
+    #[tracing::instrument(name = "Record failed second factor", skip(self, pool))]
+    pub async fn record_second_factor_failure(
+        &self,
+        user_id: Uuid,
+        ip: Option<IpAddr>,
+        pool: &PgPool,
+    ) -> Result<bool, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let failures_key = self.second_factor_failures_key(user_id);
+        let failures: u32 = redis
+            .incr(&failures_key, 1)
+            .await
+            .context("Failed to increment a failure counter in Redis")?;
+        if failures == 1 {
+            redis
+                .expire::<_, ()>(&failures_key, self.settings.failure_window_secs as usize)
+                .await
+                .context("Failed to set the expiry of a failure counter in Redis")?;
+        }
+        if failures != self.settings.max_second_factor_failures {
+            return Ok(failures > self.settings.max_second_factor_failures);
+        }
+
+        redis
+            .expire::<_, ()>(&failures_key, self.settings.lockout_secs as usize)
+            .await
+            .context("Failed to set the expiry of a failure counter in Redis")?;
+        tracing::warn!(
+            %user_id,
+            "Locking out the second factor after {} invalid codes",
+            failures
+        );
+        record_audit_event(
+            pool,
+            AuditEvent::Lockout,
+            None,
+            ip,
+            &format!(
+                "second factor of user {} locked out for {}s after {} invalid codes",
+                user_id, self.settings.lockout_secs, failures
+            ),
+        )
+        .await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(name = "Record successful second factor", skip(self))]
+    pub async fn record_second_factor_success(&self, user_id: Uuid) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        redis
+            .del::<_, ()>(self.second_factor_failures_key(user_id))
+            .await
+            .context("Failed to reset a failure counter in Redis")?;
+        Ok(())
+    }
+
+    /// A successful login forgives the username's past failures. The IP counter
+    /// is left alone, one valid account must not whitelist a password sprayer.
+    #[tracing::instrument(name = "Record successful login", skip(self))]
+    pub async fn record_success(&self, username: &str) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        redis
+            .del::<_, ()>(self.failures_key(LockoutScope::Username, &username.to_lowercase()))
+            .await
+            .context("Failed to reset a failure counter in Redis")?;
+        Ok(())
+    }
+}
+
+/// `validate_credentials` guarded by the throttle: locked out usernames or IPs
+/// are refused straight away, others wait for their progressive delay first.
+#[tracing::instrument(
+    name = "Validate credentials with throttling",
+    skip(throttle, credentials, hashing, pool)
+)]
+pub async fn validate_throttled_credentials(
+    throttle: &LoginThrottle,
+    credentials: Credentials,
+    ip: Option<IpAddr>,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<Uuid, ThrottledAuthError> {
+    let username = credentials.username.clone();
+    match throttle
+        .check(&username, ip)
+        .await
+        .map_err(AuthError::UnexpectedError)?
+    {
+        ThrottleDecision::LockedOut { retry_after } => {
+            return Err(ThrottledAuthError::LockedOut { retry_after })
+        }
+        ThrottleDecision::Allowed { delay } => {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    match validate_credentials(credentials, hashing, pool).await {
+        Ok(user_id) => {
+            throttle
+                .record_success(&username)
+                .await
+                .map_err(AuthError::UnexpectedError)?;
+            Ok(user_id)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            throttle
+                .record_failure(&username, ip, pool)
+                .await
+                .map_err(AuthError::UnexpectedError)?;
+            Err(AuthError::InvalidCredentials(e).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A bare address, or one with a port as in `1.2.3.4:80` and `[::1]:80`.
+fn parse_ip(address: &str) -> Option<IpAddr> {
+    address
+        .parse()
+        .ok()
+        .or_else(|| address.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// No delay without failures, then `base_ms` doubling with every further
+/// failure, capped at `max_ms`.
+pub fn progressive_delay(failures: u32, base_ms: u64, max_ms: u64) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    let factor = 1u64 << (failures - 1).min(32);
+    Duration::from_millis(base_ms.saturating_mul(factor).min(max_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::authentication::throttle::{parse_ip, progressive_delay};
+
+    #[test]
+    fn there_is_no_delay_without_failures() {
+        assert_eq!(progressive_delay(0, 250, 4000), Duration::ZERO);
+    }
+
+    #[test]
+    fn the_delay_doubles_with_each_failure() {
+        assert_eq!(progressive_delay(1, 250, 4000), Duration::from_millis(250));
+        assert_eq!(progressive_delay(2, 250, 4000), Duration::from_millis(500));
+        assert_eq!(progressive_delay(3, 250, 4000), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn the_delay_is_capped() {
+        assert_eq!(progressive_delay(5, 250, 4000), Duration::from_millis(4000));
+        assert_eq!(
+            progressive_delay(200, 250, 4000),
+            Duration::from_millis(4000)
+        );
+    }
+
+    #[test]
+    fn client_addresses_are_parsed_with_or_without_a_port() {
+        assert_eq!(parse_ip("203.0.113.7"), "203.0.113.7".parse().ok());
+        assert_eq!(parse_ip("203.0.113.7:443"), "203.0.113.7".parse().ok());
+        assert_eq!(parse_ip("[2001:db8::1]:443"), "2001:db8::1".parse().ok());
+        assert_eq!(parse_ip("2001:db8::1"), "2001:db8::1".parse().ok());
+        assert_eq!(parse_ip("unknown"), None);
+    }
+}
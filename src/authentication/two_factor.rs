@@ -0,0 +1,276 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::totp::{decrypt_secret, verify_code};
+use crate::domain::application::TotpEncryptionKey;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub struct TwoFactor {
+    pub encrypted_secret: Vec<u8>,
+    pub enabled: bool,
+}
+
+#[tracing::instrument(name = "Get two-factor settings", skip(pool))]
+pub async fn get_two_factor(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<TwoFactor>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret_encrypted, totp_enabled_at
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the user's two-factor settings.")?;
+    Ok(row.totp_secret_encrypted.map(|encrypted_secret| TwoFactor {
+        encrypted_secret,
+        enabled: row.totp_enabled_at.is_some(),
+    }))
+}
+
+/// Whether logging in as this user takes a second step.
+pub async fn is_two_factor_enabled(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    Ok(get_two_factor(user_id, pool)
+        .await?
+        .map(|two_factor| two_factor.enabled)
+        .unwrap_or(false))
+}
+
+/// Replaces any enrolment in progress, an already enabled secret is left alone.
+#[tracing::instrument(name = "Store pending TOTP secret", skip(encrypted_secret, pool))]
+pub async fn store_pending_secret(
+    user_id: Uuid,
+    encrypted_secret: &[u8],
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET
+            totp_secret_encrypted = $2,
+            totp_last_used_step = NULL
+        WHERE
+            user_id = $1 AND
+            totp_enabled_at IS NULL
+        "#,
+        user_id,
+        encrypted_secret
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a pending TOTP secret.")?;
+    Ok(())
+}
+
+/// Turns two-factor authentication on and returns freshly generated recovery
+/// codes, only their hashes are kept.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(pool))]
+pub async fn enable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<Vec<String>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    sqlx::query!(
+        r#"UPDATE users SET totp_enabled_at = now() WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication.")?;
+    let codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication.")?;
+    Ok(codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET
+            totp_secret_encrypted = NULL,
+            totp_enabled_at = NULL,
+            totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable two-factor authentication.")?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication.")?;
+    Ok(())
+}
+
+/// Checks a code from the authenticator app against the stored secret, whether
+/// enabled or still being enrolled.
+#[tracing::instrument(name = "Verify TOTP code", skip(code, pool, key))]
+pub async fn verify_totp(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+    key: &TotpEncryptionKey,
+) -> Result<bool, anyhow::Error> {
+    let two_factor = match get_two_factor(user_id, pool).await? {
+        Some(two_factor) => two_factor,
+        None => return Ok(false),
+    };
+    let secret = decrypt_secret(&two_factor.encrypted_secret, key)?;
+    match verify_code(&secret, code, Utc::now().timestamp()) {
+        Some(step) => consume_time_step(user_id, step, pool).await,
+        None => Ok(false),
+    }
+}
+
+/// Second login step: either a TOTP code or one of the recovery codes.
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+    key: &TotpEncryptionKey,
+) -> Result<bool, anyhow::Error> {
+    if verify_totp(user_id, code, pool, key).await? {
+        return Ok(true);
+    }
+    use_recovery_code(user_id, code, pool).await
+}
+
+// Atomically moves the last used step forward, fails if the code was already used.
+async fn consume_time_step(user_id: Uuid, step: i64, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_used_step = $2
+        WHERE
+            user_id = $1 AND
+            (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the TOTP time step.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+// Recovery codes are random enough that a fast hash is fine, as for API tokens.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    let raw: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    format!("{}-{}", &raw[..5], &raw[5..])
+}
+
+async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete previous recovery codes.")?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::text[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store recovery codes.")?;
+    Ok(codes)
+}
+
+#[tracing::instrument(name = "Use recovery code", skip(code, pool))]
+async fn use_recovery_code(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = now()
+        WHERE
+            user_id = $1 AND
+            code_hash = $2 AND
+            used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_code, hash_recovery_code};
+
+    #[test]
+    fn recovery_codes_are_matched_regardless_of_formatting() {
+        let code = generate_recovery_code();
+        assert_eq!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&code.to_uppercase().replace('-', " "))
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_unique() {
+        assert_ne!(generate_recovery_code(), generate_recovery_code());
+    }
+}
@@ -1,9 +1,17 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use argon2::Params;
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::prelude::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
 
 use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::file_sink::FileSinkTransport;
+use crate::mail::postmark::PostmarkTransport;
+use crate::mail::smtp::SmtpTransport;
+use crate::mail::transport::EmailTransport;
 
 pub enum Environment {
     Local,
@@ -34,19 +42,71 @@ impl TryFrom<String> for Environment {
     }
 }
 
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: String,
+    pub password: Secret<String>,
+    pub starttls: bool,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct FileSinkSettings {
+    pub directory: String,
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub base_url: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub send_timeout_ms: u64,
     pub sender_email: String,
     pub authorization: Secret<String>,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink: Option<FileSinkSettings>,
 }
 
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
+
+    /// Builds the email transport selected by the `transport` field.
+    pub fn transport(self) -> Result<Arc<dyn EmailTransport>, anyhow::Error> {
+        let sender = self.sender().map_err(|e| anyhow::anyhow!(e))?;
+        let timeout = std::time::Duration::from_millis(self.send_timeout_ms);
+
+        let kind = self.transport;
+        let transport: Arc<dyn EmailTransport> = match kind {
+            EmailTransportKind::Postmark => Arc::new(PostmarkTransport::new(self, sender)),
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .context("The `smtp` transport requires an [email_client.smtp] section.")?;
+                Arc::new(SmtpTransport::new(smtp, sender, timeout)?)
+            }
+            EmailTransportKind::File => {
+                let file_sink = self.file_sink.context(
+                    "The `file` transport requires an [email_client.file_sink] section.",
+                )?;
+                Arc::new(FileSinkTransport::new(file_sink, sender)?)
+            }
+        };
+        Ok(transport)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -82,7 +142,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -95,6 +155,7 @@ pub struct AppConfig {
     pub host: String,
     pub domain: String,
     pub hmac_secret: Secret<String>,
+    pub totp_encryption_key: Secret<String>,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -104,6 +165,8 @@ pub struct RedisConfig {
     pub host: String,
     pub username: String,
     pub password: Secret<String>,
+    /// Namespaces the keys we manage ourselves, so several deployments can share one Redis.
+    pub key_prefix: String,
 }
 
 impl RedisConfig {
@@ -118,12 +181,104 @@ impl RedisConfig {
     }
 }
 
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct DeliveryWorkerSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_backoff_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_backoff_secs: u64,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub token_ttl_hours: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pending_retention_hours: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub sweep_interval_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_confirmation_emails_per_hour: i64,
+    #[serde(default)]
+    pub confirmation_redirects: ConfirmationRedirects,
+}
+
+/// Where to send subscribers after they follow a confirmation link, e.g. to
+/// pages on the main website. Outcomes without a URL get our own page.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct ConfirmationRedirects {
+    pub confirmed: Option<String>,
+    pub already_confirmed: Option<String>,
+    pub invalid: Option<String>,
+    pub expired: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct EmailTemplateSettings {
+    /// Holds one sub-directory of templates per locale.
+    pub directory: String,
+    /// Used for subscribers without a locale, or whose locale has no templates.
+    pub default_locale: String,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct NewsletterSettings {
+    /// Template wrapping the HTML rendered from Markdown issues.
+    pub markdown_layout: String,
+}
+
+/// Argon2id cost parameters for newly computed password hashes. Raising them
+/// upgrades existing hashes as their owners log in.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_cost_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub time_cost: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<Params, anyhow::Error> {
+        Params::new(self.memory_cost_kib, self.time_cost, self.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct LoginThrottlingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_username: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_second_factor_failures: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_window_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_ms: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_ms: u64,
+    pub trust_proxy_headers: bool,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct Configuration {
     pub redis: RedisConfig,
     pub email_client: EmailClientSettings,
+    pub email_templates: EmailTemplateSettings,
+    pub newsletters: NewsletterSettings,
     pub database: DatabaseSettings,
     pub app: AppConfig,
+    pub delivery_worker: DeliveryWorkerSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub password_hashing: PasswordHashingSettings,
 }
 
 pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
@@ -153,5 +308,5 @@ pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
     settings.merge(config::Environment::with_prefix("app").separator("__"))?;
 
     // try converting settings into `Configuration` object.
-    return settings.try_into();
+    settings.try_into()
 }
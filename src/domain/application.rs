@@ -4,3 +4,7 @@ pub struct ApplicationBaseUrl(pub String);
 
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
+
+/// Key used to encrypt TOTP secrets at rest.
+#[derive(Clone)]
+pub struct TotpEncryptionKey(pub Secret<String>);
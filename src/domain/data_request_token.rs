@@ -0,0 +1,62 @@
+//! src/domain/data_request_token.rs
+
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::signed_token::SignedToken;
+
+/// Expiry of the links to a subscriber's data export or erasure is enforced by
+/// the `subscriber_data_requests` row they point to.
+const PURPOSE: &str = "data-request";
+
+/// Checks the token of a data export or erasure link against the request id.
+pub fn verify_data_request_token(token: &str, request_id: Uuid, secret: &HmacSecret) -> bool {
+    SignedToken::verify(PURPOSE, token, request_id, secret)
+}
+
+pub fn data_request_link(base_url: &str, request_id: Uuid, secret: &HmacSecret) -> String {
+    format!(
+        "{}/subscriptions/my-data/confirm?request_id={}&token={}",
+        base_url,
+        request_id,
+        SignedToken::generate(PURPOSE, request_id, secret).as_ref()
+    )
+}
+
+/// What is kept of an erased subscriber. Keyed so that the address cannot be
+/// recovered by hashing a list of candidates, and case-insensitive because
+/// mail providers are.
+pub fn erased_email_hash(email: &str, secret: &HmacSecret) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"erased-subscriber:");
+    mac.update(email.trim().to_lowercase().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use crate::domain::application::HmacSecret;
+
+    use super::erased_email_hash;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("a-very-secret-key".to_string()))
+    }
+
+    #[test]
+    fn erased_email_hashes_ignore_case() {
+        assert_eq!(
+            erased_email_hash("Ursula@Example.com", &secret()),
+            erased_email_hash("ursula@example.com", &secret())
+        );
+        assert_ne!(
+            erased_email_hash("ursula@example.com", &secret()),
+            erased_email_hash("le.guin@example.com", &secret())
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//! src/domain/invite_token.rs
+
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::signed_token::SignedToken;
+
+/// Expiry and single use of account setup links are enforced by the
+/// `user_invites` row they point to, not by the token itself.
+const PURPOSE: &str = "invite";
+
+/// Checks the token of an account setup link against the invite id.
+pub fn verify_invite_token(token: &str, invite_id: Uuid, secret: &HmacSecret) -> bool {
+    SignedToken::verify(PURPOSE, token, invite_id, secret)
+}
+
+pub fn invite_link(base_url: &str, invite_id: Uuid, secret: &HmacSecret) -> String {
+    format!(
+        "{}/invites/accept?invite_id={}&token={}",
+        base_url,
+        invite_id,
+        SignedToken::generate(PURPOSE, invite_id, secret).as_ref()
+    )
+}
@@ -1,6 +1,13 @@
 //! src/domain
 
 pub mod application;
+pub mod data_request_token;
+pub mod invite_token;
 pub mod new_subscriber;
+pub mod password_reset_token;
+pub mod send_at;
+pub mod signed_token;
 pub mod subscriber_email;
+pub mod subscriber_locale;
 pub mod subscriber_name;
+pub mod unsubscribe_token;
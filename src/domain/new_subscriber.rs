@@ -1,10 +1,13 @@
 //! src/domain/new_subscriber.rs
 
 use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_locale::SubscriberLocale;
 use crate::domain::subscriber_name::SubscriberName;
 
 #[derive(Debug)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    /// `None` means our default locale.
+    pub locale: Option<SubscriberLocale>,
 }
@@ -0,0 +1,24 @@
+//! src/domain/password_reset_token.rs
+
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::signed_token::SignedToken;
+
+/// Expiry and single use of password reset links are enforced by the
+/// `password_resets` row they point to, not by the token itself.
+const PURPOSE: &str = "password-reset";
+
+/// Checks the token of a password reset link against the reset id.
+pub fn verify_password_reset_token(token: &str, reset_id: Uuid, secret: &HmacSecret) -> bool {
+    SignedToken::verify(PURPOSE, token, reset_id, secret)
+}
+
+pub fn password_reset_link(base_url: &str, reset_id: Uuid, secret: &HmacSecret) -> String {
+    format!(
+        "{}/password-reset/confirm?reset_id={}&token={}",
+        base_url,
+        reset_id,
+        SignedToken::generate(PURPOSE, reset_id, secret).as_ref()
+    )
+}
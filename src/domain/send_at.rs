@@ -0,0 +1,96 @@
+//! src/domain/send_at.rs
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// When a scheduled newsletter issue should go out, always in the future.
+#[derive(Debug, Clone, Copy)]
+pub struct SendAt(DateTime<Utc>);
+
+impl SendAt {
+    /// An RFC 3339 timestamp, the offset is required: `2022-09-19T09:00:00+02:00`.
+    pub fn parse(s: &str) -> Result<SendAt, String> {
+        let time = DateTime::parse_from_rfc3339(s.trim())
+            .map_err(|_| format!("{} is not an RFC 3339 timestamp with a time zone.", s))?;
+        Self::in_the_future(time.with_timezone(&Utc))
+    }
+
+    /// What the admin form sends: a `datetime-local` value such as `2022-09-19T09:00`
+    /// and the IANA time zone it is in, e.g. `Europe/Paris`. Daylight saving time
+    /// is accounted for.
+    pub fn parse_local(local: &str, timezone: &str) -> Result<SendAt, String> {
+        Self::in_the_future(local_to_utc(local, timezone)?)
+    }
+
+    fn in_the_future(time: DateTime<Utc>) -> Result<SendAt, String> {
+        if time <= Utc::now() {
+            return Err(format!("{} is in the past.", time.to_rfc3339()));
+        }
+        Ok(Self(time))
+    }
+}
+
+impl AsRef<DateTime<Utc>> for SendAt {
+    fn as_ref(&self) -> &DateTime<Utc> {
+        &self.0
+    }
+}
+
+fn local_to_utc(local: &str, timezone: &str) -> Result<DateTime<Utc>, String> {
+    let local = local.trim();
+    let time = NaiveDateTime::parse_from_str(local, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(local, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| format!("{} is not a valid date and time.", local))?;
+    let timezone: Tz = timezone
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} is not a known time zone.", timezone))?;
+    // Skipped or repeated when the clocks change.
+    let time = timezone
+        .from_local_datetime(&time)
+        .single()
+        .ok_or_else(|| {
+            format!(
+                "{} does not exist, or is ambiguous, in {}.",
+                local, timezone
+            )
+        })?;
+    Ok(time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_ok};
+
+    use super::{local_to_utc, SendAt};
+
+    #[test]
+    fn timestamps_need_an_offset() {
+        let send_at = assert_ok!(SendAt::parse("2999-09-19T09:00:00+02:00"));
+        assert_eq!(*send_at.as_ref(), Utc.ymd(2999, 9, 19).and_hms(7, 0, 0));
+        assert_err!(SendAt::parse("2999-09-19T09:00:00"));
+        assert_err!(SendAt::parse("next monday"));
+    }
+
+    #[test]
+    fn times_in_the_past_are_rejected() {
+        assert_err!(SendAt::parse("2000-01-01T00:00:00Z"));
+        assert_err!(SendAt::parse_local("2000-01-01T00:00", "UTC"));
+    }
+
+    #[test]
+    fn local_times_follow_daylight_saving_time() {
+        let winter = assert_ok!(local_to_utc("2022-01-07T09:00", "Europe/Paris"));
+        let summer = assert_ok!(local_to_utc("2022-07-07T09:00:00", "Europe/Paris"));
+        assert_eq!(winter, Utc.ymd(2022, 1, 7).and_hms(8, 0, 0));
+        assert_eq!(summer, Utc.ymd(2022, 7, 7).and_hms(7, 0, 0));
+    }
+
+    #[test]
+    fn unknown_time_zones_and_skipped_times_are_rejected() {
+        assert_err!(local_to_utc("2022-01-07T09:00", "Europe/Atlantis"));
+        // The clocks went from 02:00 to 03:00 that night.
+        assert_err!(local_to_utc("2022-03-27T02:30", "Europe/Paris"));
+    }
+}
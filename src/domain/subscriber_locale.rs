@@ -0,0 +1,73 @@
+//! src/domain/subscriber_locale.rs
+
+/// A language tag such as `en` or `pt-BR`, picking which variant of our emails
+/// a subscriber gets. Only the language and an optional region are supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriberLocale(String);
+
+impl SubscriberLocale {
+    pub fn parse(s: String) -> Result<SubscriberLocale, String> {
+        let invalid = || format!("{} is not a supported locale.", s);
+        let (language, region) = match s.split_once(['-', '_']) {
+            Some((language, region)) => (language, Some(region)),
+            None => (s.as_str(), None),
+        };
+        if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            return Err(invalid());
+        }
+        let mut locale = language.to_ascii_lowercase();
+        if let Some(region) = region {
+            if region.len() != 2 || !region.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(invalid());
+            }
+            locale.push('-');
+            locale.push_str(&region.to_ascii_uppercase());
+        }
+        Ok(Self(locale))
+    }
+
+    /// The locale itself, then the bare language if it has a region.
+    pub fn fallbacks(&self) -> impl Iterator<Item = &str> {
+        let language = self.0.split('-').next().filter(|l| *l != self.0);
+        std::iter::once(self.0.as_str()).chain(language)
+    }
+}
+
+impl AsRef<str> for SubscriberLocale {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::SubscriberLocale;
+
+    #[test]
+    fn languages_and_regions_are_normalised() {
+        let locale = assert_ok!(SubscriberLocale::parse("pt_br".to_string()));
+        assert_eq!(locale.as_ref(), "pt-BR");
+        let locale = assert_ok!(SubscriberLocale::parse("FR".to_string()));
+        assert_eq!(locale.as_ref(), "fr");
+    }
+
+    #[test]
+    fn malformed_locales_are_rejected() {
+        assert_err!(SubscriberLocale::parse("".to_string()));
+        assert_err!(SubscriberLocale::parse("english".to_string()));
+        assert_err!(SubscriberLocale::parse("en-".to_string()));
+        assert_err!(SubscriberLocale::parse("en-USA".to_string()));
+        assert_err!(SubscriberLocale::parse("../en".to_string()));
+    }
+
+    #[test]
+    fn a_regional_locale_falls_back_to_its_language() {
+        let locale = SubscriberLocale::parse("pt-BR".to_string()).unwrap();
+        assert_eq!(locale.fallbacks().collect::<Vec<_>>(), vec!["pt-BR", "pt"]);
+        let locale = SubscriberLocale::parse("fr".to_string()).unwrap();
+        assert_eq!(locale.fallbacks().collect::<Vec<_>>(), vec!["fr"]);
+    }
+}
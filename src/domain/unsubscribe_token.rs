@@ -0,0 +1,24 @@
+//! src/domain/unsubscribe_token.rs
+
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::signed_token::SignedToken;
+
+/// Unsubscribe tokens never expire and are not stored: a subscriber should be
+/// able to leave using any email we ever sent them.
+const PURPOSE: &str = "unsubscribe";
+
+/// Checks the token of an unsubscribe link against the subscriber id.
+pub fn verify_unsubscribe_token(token: &str, subscriber_id: Uuid, secret: &HmacSecret) -> bool {
+    SignedToken::verify(PURPOSE, token, subscriber_id, secret)
+}
+
+pub fn unsubscribe_link(base_url: &str, subscriber_id: Uuid, secret: &HmacSecret) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+        base_url,
+        subscriber_id,
+        SignedToken::generate(PURPOSE, subscriber_id, secret).as_ref()
+    )
+}
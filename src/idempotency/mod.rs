@@ -0,0 +1,4 @@
+//! src/idempotency
+
+pub mod key;
+pub mod persistence;
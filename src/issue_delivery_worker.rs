@@ -1,20 +1,64 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
 use sqlx::{PgPool, Postgres, Transaction};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{field::display, Span};
 use uuid::Uuid;
 
-use crate::{configuration::Settings, startup::get_connection_pool};
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::config::{Configuration, DeliveryWorkerSettings};
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_name::SubscriberName;
+use crate::domain::unsubscribe_token::unsubscribe_link;
+use crate::mail::placeholders::MergeFields;
+use crate::mail::transport::{EmailMessage, EmailTransport};
+use crate::routes::newsletter::enqueue_delivery_tasks;
+use crate::startup::get_connection_pool;
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+pub async fn run_worker_until_stopped(configuration: Configuration) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    let email_client = configuration.email_client.transport()?;
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.delivery_worker,
+        ApplicationBaseUrl(configuration.app.domain),
+        HmacSecret(configuration.app.hmac_secret),
+    )
+    .await
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailTransport>,
+    settings: DeliveryWorkerSettings,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    let mut next_release = Instant::now();
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        // Checked on a timer rather than when the queue is empty, a large issue
+        // keeps the queue busy for a while and must not hold back the next one.
+        if Instant::now() >= next_release {
+            if let Err(e) = release_due_issues(&pool).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to release the scheduled newsletter issues that are due",
+                );
+            }
+            next_release = Instant::now() + Duration::from_secs(10);
+        }
+        match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &settings,
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
             Ok(ExecutionOutcome::EmptyQueue) => {
                 tokio::time::sleep(Duration::from_secs(10)).await;
             }
@@ -27,6 +71,39 @@ async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyh
     }
 }
 
+/// Publishes the scheduled issues whose `send_at` has passed and enqueues their
+/// deliveries. Issues being cancelled or rescheduled at the same time are left
+/// for the next run.
+#[tracing::instrument(skip_all)]
+pub async fn release_due_issues(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issues = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE newsletter_issue_id IN (
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            WHERE status = 'scheduled' AND send_at <= now()
+            FOR UPDATE
+            SKIP LOCKED
+        )
+        RETURNING newsletter_issue_id
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    for issue in issues {
+        tracing::info!(
+            newsletter_issue_id = %issue.newsletter_issue_id,
+            "Releasing a scheduled issue."
+        );
+        enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
@@ -36,40 +113,96 @@ pub enum ExecutionOutcome {
 skip_all,
 fields(
 newsletter_issue_id = tracing::field::Empty,
-subscriber_email = tracing::field::Empty
+subscriber_email = tracing::field::Empty,
+n_retries = tracing::field::Empty
 ),
 err
 )]
 pub async fn try_execute_task(
     pool: &PgPool,
-    email_client: &EmailClient,
+    email_client: &dyn EmailTransport,
+    settings: &DeliveryWorkerSettings,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(pool).await?;
     if task.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
+    let (transaction, task) = task.unwrap();
     Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
-                .await
-            {
-                tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber. \
-                        Skipping.",
-                );
+        .record("newsletter_issue_id", &display(task.issue_id))
+        .record("subscriber_email", &display(&task.email))
+        .record("n_retries", &display(task.n_retries));
+
+    // They may have unsubscribed since the issue was published.
+    let subscriber = match get_confirmed_subscriber(pool, &task.email).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+            delete_task(transaction, task.issue_id, &task.email).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let contact_details = SubscriberEmail::parse(task.email.clone())
+        .and_then(|email| SubscriberName::parse(subscriber.name.clone()).map(|name| (email, name)));
+    match contact_details {
+        Ok((email, name)) => {
+            let issue = get_issue(pool, task.issue_id).await?;
+            let unsubscribe_url = unsubscribe_link(&base_url.0, subscriber.id, hmac_secret);
+            let fields = MergeFields {
+                name: &name,
+                unsubscribe_url: &unsubscribe_url,
+                confirmed_at: subscriber.confirmed_at,
+            };
+            let subject = fields.render_text(&issue.title);
+            let html_content = format!(
+                "{}<p><a href=\"{}\">Unsubscribe</a></p>",
+                fields.render_html(&issue.html_content),
+                unsubscribe_url
+            );
+            let text_content = format!(
+                "{}\n\nUnsubscribe: {}",
+                fields.render_text(&issue.text_content),
+                unsubscribe_url
+            );
+            let message = EmailMessage {
+                recipient: &email,
+                subject: &subject,
+                html_content: &html_content,
+                text_content: &text_content,
+                // RFC 8058 one-click unsubscribe.
+                headers: vec![
+                    ("List-Unsubscribe", format!("<{}>", unsubscribe_url)),
+                    (
+                        "List-Unsubscribe-Post",
+                        "List-Unsubscribe=One-Click".to_string(),
+                    ),
+                ],
+            };
+            if let Err(e) = email_client.send(&message).await {
+                let n_retries = task.n_retries + 1;
+                if n_retries >= settings.max_retries {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                            Retry budget exhausted, moving it to the dead letters.",
+                    );
+                    dead_letter_task(transaction, &task, n_retries, &e.to_string()).await?;
+                } else {
+                    let backoff = compute_backoff(n_retries, settings);
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                            Retrying in {} seconds.",
+                        backoff.as_secs(),
+                    );
+                    retry_task(transaction, &task, n_retries, backoff).await?;
+                }
+                return Ok(ExecutionOutcome::TaskCompleted);
             }
         }
         Err(e) => {
@@ -81,24 +214,46 @@ pub async fn try_execute_task(
             );
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
+    delete_task(transaction, task.issue_id, &task.email).await?;
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+/// Exponential backoff with jitter: the delay doubles on every attempt, is capped
+/// at `max_backoff_secs` and then randomly shaved by up to half so that tasks
+/// which failed together (e.g. during a provider outage) do not retry in lockstep.
+pub fn compute_backoff(n_retries: i32, settings: &DeliveryWorkerSettings) -> Duration {
+    let exponent = n_retries.saturating_sub(1).max(0) as u32;
+    let backoff = 2u64
+        .checked_pow(exponent)
+        .and_then(|factor| settings.base_backoff_secs.checked_mul(factor))
+        .unwrap_or(settings.max_backoff_secs)
+        .min(settings.max_backoff_secs);
+    let jitter = rand::thread_rng().gen_range(0..=backoff / 2);
+    Duration::from_secs(backoff - jitter)
+}
+
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct DeliveryTask {
+    issue_id: Uuid,
+    email: String,
+    n_retries: i32,
+}
+
 #[tracing::instrument(skip_all)]
 async fn dequeue_task(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
     let r = sqlx::query!(
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT newsletter_issue_id, subscriber_email, n_retries
         FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        ORDER BY execute_after
+        LIMIT 1
         FOR UPDATE
         SKIP LOCKED
-        LIMIT 1
         "#,
     )
     .fetch_optional(&mut transaction)
@@ -106,8 +261,11 @@ async fn dequeue_task(
     if let Some(r) = r {
         Ok(Some((
             transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
+            DeliveryTask {
+                issue_id: r.newsletter_issue_id,
+                email: r.subscriber_email,
+                n_retries: r.n_retries,
+            },
         )))
     } else {
         Ok(None)
@@ -123,9 +281,9 @@ async fn delete_task(
     sqlx::query!(
         r#"
         DELETE FROM issue_delivery_queue
-        WHERE 
+        WHERE
             newsletter_issue_id = $1 AND
-            subscriber_email = $2 
+            subscriber_email = $2
         "#,
         issue_id,
         email
@@ -136,6 +294,107 @@ async fn delete_task(
     Ok(())
 }
 
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    n_retries: i32,
+    backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(backoff)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = $3,
+            execute_after = $4
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+        n_retries,
+        execute_after
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    n_retries: i32,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.issue_id,
+        task.email,
+        n_retries,
+        last_error
+    )
+    .execute(&mut transaction)
+    .await?;
+    delete_task(transaction, task.issue_id, &task.email).await
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+    confirmed_at: DateTime<Utc>,
+}
+
+/// Subscribers imported as confirmed never went through a confirmation, their
+/// sign-up date stands in for it.
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT
+            s.id,
+            s.name,
+            COALESCE(
+                (
+                    SELECT MAX(e.occurred_at)
+                    FROM subscription_events e
+                    WHERE e.subscription_id = s.id AND e.event = 'confirmed'
+                ),
+                s.subscribed_at
+            ) AS "confirmed_at!"
+        FROM subscriptions s
+        WHERE
+            s.email = $1 AND
+            s.status = 'confirmed'
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber)
+}
+
 struct NewsletterIssue {
     title: String,
     text_content: String,
@@ -158,3 +417,42 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     .await?;
     Ok(issue)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::DeliveryWorkerSettings;
+
+    use super::compute_backoff;
+
+    fn settings() -> DeliveryWorkerSettings {
+        DeliveryWorkerSettings {
+            max_retries: 5,
+            base_backoff_secs: 10,
+            max_backoff_secs: 100,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_with_every_retry() {
+        for (n_retries, upper_bound) in [(1, 10), (2, 20), (3, 40), (4, 80)] {
+            let backoff = compute_backoff(n_retries, &settings());
+            assert!(backoff <= Duration::from_secs(upper_bound));
+            assert!(backoff >= Duration::from_secs(upper_bound / 2));
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let backoff = compute_backoff(30, &settings());
+        assert!(backoff <= Duration::from_secs(100));
+        assert!(backoff >= Duration::from_secs(50));
+    }
+
+    #[test]
+    fn backoff_does_not_overflow_for_huge_retry_counts() {
+        let backoff = compute_backoff(i32::MAX, &settings());
+        assert!(backoff <= Duration::from_secs(100));
+    }
+}
@@ -1,10 +1,14 @@
 pub mod authentication;
 pub mod config;
 pub mod domain;
+pub mod idempotency;
+pub mod issue_delivery_worker;
 pub mod mail;
 pub mod routes;
 pub mod run;
 pub mod session_state;
 pub mod startup;
+pub mod subscription_history;
+pub mod subscription_sweeper;
 pub mod telemetry;
 pub mod utils;
@@ -0,0 +1,92 @@
+//! src/mail/file_sink.rs
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::FileSinkSettings;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::smtp::build_message;
+use crate::mail::transport::{EmailMessage, EmailTransport};
+
+/// Writes every email as an `.eml` file into a local directory instead of sending it,
+/// handy during development to look at what would have gone out.
+pub struct FileSinkTransport {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkTransport {
+    pub fn new(settings: FileSinkSettings, sender: SubscriberEmail) -> Result<Self, anyhow::Error> {
+        let directory = PathBuf::from(settings.directory);
+        std::fs::create_dir_all(&directory).with_context(|| {
+            format!(
+                "Failed to create the email sink directory {}",
+                directory.display()
+            )
+        })?;
+        Ok(Self { directory, sender })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileSinkTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error> {
+        let email = build_message(&self.sender, message)?;
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.f"),
+            Uuid::new_v4()
+        );
+        let path = self.directory.join(file_name);
+        tokio::fs::write(&path, email.formatted())
+            .await
+            .with_context(|| format!("Failed to write email to {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use uuid::Uuid;
+
+    use crate::config::FileSinkSettings;
+    use crate::domain::subscriber_email::SubscriberEmail;
+    use crate::mail::transport::EmailTransport;
+
+    use super::FileSinkTransport;
+
+    #[tokio::test]
+    async fn send_email_writes_one_file_per_email() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let recipient = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let transport = FileSinkTransport::new(
+            FileSinkSettings {
+                directory: directory.display().to_string(),
+            },
+            sender,
+        )
+        .unwrap();
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient, "A subject", "<p>Some html</p>", "Some text")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let content = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(content.contains("Subject: A subject"));
+        assert!(content.contains(recipient.as_ref()));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
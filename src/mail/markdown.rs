@@ -0,0 +1,292 @@
+//! src/mail/markdown.rs
+
+use anyhow::Context;
+use pulldown_cmark::{Event, Parser, Tag};
+use tera::Tera;
+
+use crate::config::NewsletterSettings;
+
+const LAYOUT: &str = "layout.html";
+
+pub struct RenderedContent {
+    pub html: String,
+    pub text: String,
+}
+
+/// Turns a Markdown newsletter body into sanitized HTML, wrapped in the layout
+/// from the configuration, and into a plain text version of the same content.
+pub struct MarkdownRenderer {
+    tera: Tera,
+}
+
+impl MarkdownRenderer {
+    pub fn load(settings: &NewsletterSettings) -> Result<Self, anyhow::Error> {
+        let mut tera = Tera::default();
+        tera.add_template_file(&settings.markdown_layout, Some(LAYOUT))
+            .with_context(|| {
+                format!(
+                    "Failed to compile the newsletter layout in {}",
+                    settings.markdown_layout
+                )
+            })?;
+        let renderer = Self { tera };
+        // Fail at startup rather than on the first publication.
+        renderer.render("", "")?;
+        Ok(renderer)
+    }
+
+    pub fn render(&self, title: &str, markdown: &str) -> Result<RenderedContent, anyhow::Error> {
+        let mut body = String::new();
+        pulldown_cmark::html::push_html(&mut body, Parser::new(markdown));
+        // Markdown lets raw HTML through, scripts and friends included.
+        let body = ammonia::clean(&body);
+
+        let mut context = tera::Context::new();
+        context.insert("title", title);
+        context.insert("content", &body);
+        let html = self
+            .tera
+            .render(LAYOUT, &context)
+            .context("Failed to render the newsletter layout")?;
+
+        Ok(RenderedContent {
+            html,
+            text: to_plain_text(markdown),
+        })
+    }
+}
+
+/// A readable rendition of the Markdown for email clients that do not show HTML:
+/// underlined headings, `- ` bullets, `> ` quotes, indented code and links
+/// followed by their URL. Raw HTML is dropped.
+fn to_plain_text(markdown: &str) -> String {
+    let mut writer = TextWriter::default();
+    for event in Parser::new(markdown) {
+        writer.write(event);
+    }
+    let mut text = writer.out.trim_end().to_string();
+    text.push('\n');
+    text
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    /// Where the block or inline element being written started, so that it can
+    /// be decorated once we know its content.
+    starts: Vec<usize>,
+    /// The next number of each ordered list we are in, `None` for bullets.
+    lists: Vec<Option<u64>>,
+    /// Where the content of the current list item starts, after its marker.
+    item_content: usize,
+}
+
+impl TextWriter {
+    fn write(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Code(text) => self.out.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => self.out.push('\n'),
+            Event::Rule => {
+                self.block_break();
+                self.out.push_str("----------");
+            }
+            Event::TaskListMarker(checked) => {
+                self.out.push_str(if checked { "[x] " } else { "[ ] " })
+            }
+            Event::Html(_) | Event::FootnoteReference(_) => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            // Loose lists wrap items in paragraphs, only the second one of an
+            // item needs a line of its own.
+            Tag::Paragraph if !self.lists.is_empty() && self.out.len() != self.item_content => {
+                self.out.push('\n');
+                self.indent();
+            }
+            Tag::Paragraph if !self.lists.is_empty() => {}
+            Tag::List(first_number) => {
+                if self.lists.is_empty() {
+                    self.block_break();
+                }
+                self.lists.push(first_number);
+            }
+            Tag::Item => {
+                if !self.out.is_empty() && !self.out.ends_with('\n') {
+                    self.out.push('\n');
+                }
+                self.indent();
+                match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        self.out.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => self.out.push_str("- "),
+                }
+                self.item_content = self.out.len();
+            }
+            Tag::Link(..) | Tag::Image(..) => self.starts.push(self.out.len()),
+            Tag::Heading(..) | Tag::BlockQuote | Tag::CodeBlock(_) => {
+                self.block_break();
+                self.starts.push(self.out.len());
+            }
+            Tag::Paragraph => self.block_break(),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::List(_) => {
+                self.lists.pop();
+            }
+            Tag::Heading(level, ..) => {
+                let start = self.starts.pop().unwrap_or_default();
+                let length = self.out[start..].chars().count();
+                let underline = if level == pulldown_cmark::HeadingLevel::H1 {
+                    "="
+                } else {
+                    "-"
+                };
+                self.out.push('\n');
+                self.out.push_str(&underline.repeat(length));
+            }
+            Tag::BlockQuote => self.prefix_lines_since_start(|line| {
+                if line.is_empty() {
+                    ">".to_string()
+                } else {
+                    format!("> {}", line)
+                }
+            }),
+            Tag::CodeBlock(_) => self.prefix_lines_since_start(|line| {
+                if line.is_empty() {
+                    String::new()
+                } else {
+                    format!("    {}", line)
+                }
+            }),
+            Tag::Link(_, destination, _) | Tag::Image(_, destination, _) => {
+                let start = self.starts.pop().unwrap_or_default();
+                let label = &self.out[start..];
+                // Autolinks already show their URL.
+                if label != destination.trim_start_matches("mailto:") {
+                    if label.is_empty() {
+                        self.out.push_str(&destination);
+                    } else {
+                        self.out.push_str(&format!(" ({})", destination));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Leaves exactly one empty line between the previous block and the next.
+    fn block_break(&mut self) {
+        if self.out.is_empty() {
+            return;
+        }
+        let length = self.out.trim_end_matches('\n').len();
+        self.out.truncate(length);
+        self.out.push_str("\n\n");
+    }
+
+    fn indent(&mut self) {
+        let depth = self.lists.len().saturating_sub(1);
+        self.out.push_str(&"  ".repeat(depth));
+    }
+
+    fn prefix_lines_since_start(&mut self, prefix: impl Fn(&str) -> String) {
+        let start = self.starts.pop().unwrap_or_default();
+        let block = self.out.split_off(start);
+        let lines: Vec<String> = block.trim_end_matches('\n').lines().map(prefix).collect();
+        self.out.push_str(&lines.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::NewsletterSettings;
+
+    use super::{to_plain_text, MarkdownRenderer};
+
+    fn renderer() -> MarkdownRenderer {
+        MarkdownRenderer::load(&NewsletterSettings {
+            markdown_layout: "templates/newsletter/layout.html".into(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn markdown_is_rendered_inside_the_layout() {
+        let content = renderer()
+            .render("Issue #1", "# Hello\n\nSome *news*.")
+            .unwrap();
+        assert!(content.html.contains("<h1>Hello</h1>"));
+        assert!(content.html.contains("<em>news</em>"));
+        assert!(content.html.contains("Issue #1"));
+    }
+
+    #[test]
+    fn raw_html_is_sanitized() {
+        let markdown = "Hi <script>alert('pwned')</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"x()\">link</a>";
+        let content = renderer().render("Title", markdown).unwrap();
+        assert!(!content.html.contains("<script"));
+        assert!(!content.html.contains("javascript:"));
+        assert!(!content.html.contains("onclick"));
+        assert!(!content.text.contains("<script"));
+    }
+
+    #[test]
+    fn the_title_is_escaped_in_the_layout() {
+        let content = renderer().render("<b>Tom & Jerry</b>", "Hi").unwrap();
+        assert!(content.html.contains("&lt;b&gt;Tom &amp; Jerry&lt;"));
+    }
+
+    #[test]
+    fn the_plain_text_version_is_readable() {
+        let markdown = "\
+# News
+
+A [link](https://example.com) and <https://example.org>.
+
+- one
+- two
+  1. nested
+
+> quoted
+> text
+
+    let code = 1;
+";
+        assert_eq!(
+            to_plain_text(markdown),
+            "\
+News
+====
+
+A link (https://example.com) and https://example.org.
+
+- one
+- two
+  1. nested
+
+> quoted
+> text
+
+    let code = 1;
+"
+        );
+    }
+
+    #[test]
+    fn a_missing_layout_is_rejected() {
+        assert!(MarkdownRenderer::load(&NewsletterSettings {
+            markdown_layout: "templates/newsletter/missing.html".into(),
+        })
+        .is_err());
+    }
+}
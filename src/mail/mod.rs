@@ -1,3 +1,9 @@
 //! src/mod
 
-pub mod send_email;
+pub mod file_sink;
+pub mod markdown;
+pub mod placeholders;
+pub mod postmark;
+pub mod smtp;
+pub mod templates;
+pub mod transport;
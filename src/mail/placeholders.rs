@@ -0,0 +1,126 @@
+//! src/mail/placeholders.rs
+
+use chrono::{DateTime, Utc};
+
+use crate::domain::subscriber_name::SubscriberName;
+
+/// Merge fields a newsletter issue can use, e.g. `Hi {{ name }}!`. They are
+/// filled in for every recipient when the issue is delivered.
+pub const PLACEHOLDERS: [&str; 3] = ["name", "unsubscribe_url", "confirmed_at"];
+
+/// What the placeholders stand for, for a single recipient.
+pub struct MergeFields<'a> {
+    pub name: &'a SubscriberName,
+    pub unsubscribe_url: &'a str,
+    pub confirmed_at: DateTime<Utc>,
+}
+
+impl MergeFields<'_> {
+    pub fn render_text(&self, content: &str) -> String {
+        substitute(content, |placeholder| self.value(placeholder))
+    }
+
+    /// Values are escaped, a name like `Tom & Jerry` is text, not markup.
+    pub fn render_html(&self, content: &str) -> String {
+        substitute(content, |placeholder| {
+            self.value(placeholder)
+                .map(|value| htmlescape::encode_minimal(&value))
+        })
+    }
+
+    fn value(&self, placeholder: &str) -> Option<String> {
+        match placeholder {
+            "name" => Some(self.name.as_ref().to_string()),
+            "unsubscribe_url" => Some(self.unsubscribe_url.to_string()),
+            "confirmed_at" => Some(self.confirmed_at.format("%B %-d, %Y").to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Rejects content using placeholders we would not know how to fill in, rather
+/// than sending them as is to every subscriber.
+pub fn validate_placeholders(content: &str) -> Result<(), String> {
+    let mut unknown = Vec::new();
+    substitute(content, |placeholder| {
+        if !PLACEHOLDERS.contains(&placeholder) {
+            unknown.push(format!("{{{{ {} }}}}", placeholder));
+        }
+        None
+    });
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let known: Vec<String> = PLACEHOLDERS
+        .iter()
+        .map(|placeholder| format!("{{{{ {} }}}}", placeholder))
+        .collect();
+    Err(format!(
+        "Unknown placeholder(s): {}. The available ones are {}.",
+        unknown.join(", "),
+        known.join(", ")
+    ))
+}
+
+/// Replaces every `{{ placeholder }}` that `resolve` knows about, the others are
+/// left untouched.
+fn substitute(content: &str, mut resolve: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(length) => start + length + 2,
+            None => break,
+        };
+        let placeholder = &rest[start..end];
+        output.push_str(&rest[..start]);
+        match resolve(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(placeholder),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_ok};
+
+    use crate::domain::subscriber_name::SubscriberName;
+
+    use super::{validate_placeholders, MergeFields};
+
+    #[test]
+    fn placeholders_are_filled_in_and_escaped_in_html() {
+        let name = SubscriberName::parse("Tom & Jerry".to_string()).unwrap();
+        let fields = MergeFields {
+            name: &name,
+            unsubscribe_url: "https://example.com/unsubscribe?a=1&b=2",
+            confirmed_at: Utc.ymd(2022, 9, 12).and_hms(10, 0, 0),
+        };
+        let content =
+            "Hi {{ name }}, since {{confirmed_at}}. <a href=\"{{ unsubscribe_url }}\">Bye</a>";
+        assert_eq!(
+            fields.render_text(content),
+            "Hi Tom & Jerry, since September 12, 2022. \
+                <a href=\"https://example.com/unsubscribe?a=1&b=2\">Bye</a>"
+        );
+        assert_eq!(
+            fields.render_html(content),
+            "Hi Tom &amp; Jerry, since September 12, 2022. \
+                <a href=\"https://example.com/unsubscribe?a=1&amp;b=2\">Bye</a>"
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_ok!(validate_placeholders("Hi {{ name }}, no braces here } {"));
+        assert_ok!(validate_placeholders("An unterminated {{ is just text"));
+        let error = assert_err!(validate_placeholders("Hi {{ first_name }}"));
+        assert!(error.contains("{{ first_name }}"));
+        assert_err!(validate_placeholders("Hi {{}}"));
+    }
+}
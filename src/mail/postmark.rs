@@ -1,10 +1,12 @@
-//! src/mail/send_mail.rs
+//! src/mail/postmark.rs
 
+use anyhow::Context;
 use reqwest::Client;
 use secrecy::ExposeSecret;
 
 use crate::config::EmailClientSettings;
 use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::transport::{EmailMessage, EmailTransport};
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -14,15 +16,25 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
 }
 
-pub struct EmailClient {
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+/// Sends emails through Postmark's HTTP API.
+pub struct PostmarkTransport {
     http_client: Client,
     sender: SubscriberEmail,
     email_settings: EmailClientSettings,
 }
 
-impl EmailClient {
+impl PostmarkTransport {
     pub fn new(email_settings: EmailClientSettings, sender: SubscriberEmail) -> Self {
         Self {
             http_client: Client::builder()
@@ -35,21 +47,26 @@ impl EmailClient {
             sender,
         }
     }
+}
 
-    pub async fn send_email(
-        &self,
-        recipient: &SubscriberEmail,
-        subject: &str,
-        html_content: &str,
-        text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error> {
         let url = format!("{}/email", self.email_settings.base_url);
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject,
-            html_body: html_content,
-            text_body: text_content,
+            to: message.recipient.as_ref(),
+            subject: message.subject,
+            html_body: message.html_content,
+            text_body: message.text_content,
+            headers: message
+                .headers
+                .iter()
+                .map(|(name, value)| PostmarkHeader {
+                    name,
+                    value: value.as_str(),
+                })
+                .collect(),
         };
 
         self.http_client
@@ -60,8 +77,10 @@ impl EmailClient {
             )
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .context("Failed to send the email request to Postmark.")?
+            .error_for_status()
+            .context("Postmark rejected the email request.")?;
 
         Ok(())
     }
@@ -77,10 +96,11 @@ mod tests {
     use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
-    use crate::config::EmailClientSettings;
+    use crate::config::{EmailClientSettings, EmailTransportKind};
     use crate::domain::subscriber_email::SubscriberEmail;
+    use crate::mail::transport::EmailTransport;
 
-    use super::EmailClient;
+    use super::PostmarkTransport;
 
     struct SendEmailBodyMatcher;
 
@@ -111,16 +131,19 @@ mod tests {
         SubscriberEmail::parse(SafeEmail().fake()).unwrap()
     }
 
-    fn email_client(server_uri: String) -> EmailClient {
+    fn email_client(server_uri: String) -> PostmarkTransport {
         let email_settings = EmailClientSettings {
+            transport: EmailTransportKind::Postmark,
             base_url: server_uri,
             send_timeout_ms: 150,
             sender_email: SafeEmail().fake(),
             authorization: Secret::new(Faker.fake()),
+            smtp: None,
+            file_sink: None,
         };
         let sender = SubscriberEmail::parse(email_settings.sender_email.clone()).unwrap();
 
-        EmailClient::new(email_settings, sender)
+        PostmarkTransport::new(email_settings, sender)
     }
 
     #[tokio::test]
@@ -0,0 +1,124 @@
+//! src/mail/smtp.rs
+
+use std::time::Duration;
+
+use anyhow::Context;
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::ExposeSecret;
+
+use crate::config::SmtpSettings;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::transport::{EmailMessage, EmailTransport};
+
+/// Sends emails to an SMTP relay, upgrading the connection with STARTTLS
+/// unless `smtp.starttls` is turned off (e.g. a local mailcatcher).
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        settings: SmtpSettings,
+        sender: SubscriberEmail,
+        timeout: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = if settings.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)
+                .context("Failed to configure the SMTP relay.")?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+        };
+
+        let mailer = builder
+            .port(settings.port)
+            .credentials(Credentials::new(
+                settings.username,
+                settings.password.expose_secret().to_owned(),
+            ))
+            .timeout(Some(timeout))
+            .build();
+
+        Ok(Self { mailer, sender })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error> {
+        let email = build_message(&self.sender, message)?;
+        self.mailer
+            .send(email)
+            .await
+            .context("The SMTP relay rejected the email.")?;
+        Ok(())
+    }
+}
+
+/// Builds a `multipart/alternative` MIME message out of an `EmailMessage`.
+pub(crate) fn build_message(
+    sender: &SubscriberEmail,
+    message: &EmailMessage<'_>,
+) -> Result<Message, anyhow::Error> {
+    let from: Mailbox = sender
+        .as_ref()
+        .parse()
+        .context("The sender email is not a valid mailbox.")?;
+    let to: Mailbox = message
+        .recipient
+        .as_ref()
+        .parse()
+        .context("The recipient email is not a valid mailbox.")?;
+
+    let mut builder = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(message.subject);
+    for (name, value) in &message.headers {
+        builder = match *name {
+            ListUnsubscribe::NAME => builder.header(ListUnsubscribe(value.clone())),
+            ListUnsubscribePost::NAME => builder.header(ListUnsubscribePost(value.clone())),
+            other => anyhow::bail!("The {} header is not supported over SMTP.", other),
+        };
+    }
+
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            message.text_content.to_owned(),
+            message.html_content.to_owned(),
+        ))
+        .context("Failed to build the email message.")
+}
+
+/// lettre only sets headers through a type implementing `Header`, one per name.
+macro_rules! raw_header {
+    ($type:ident, $name:literal) => {
+        #[derive(Clone)]
+        struct $type(String);
+
+        impl $type {
+            const NAME: &'static str = $name;
+        }
+
+        impl Header for $type {
+            fn name() -> HeaderName {
+                HeaderName::new_from_ascii_str(Self::NAME)
+            }
+
+            fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+                Ok(Self(s.to_owned()))
+            }
+
+            fn display(&self) -> HeaderValue {
+                HeaderValue::new(Self::name(), self.0.clone())
+            }
+        }
+    };
+}
+
+// RFC 8058 one-click unsubscribe.
+raw_header!(ListUnsubscribe, "List-Unsubscribe");
+raw_header!(ListUnsubscribePost, "List-Unsubscribe-Post");
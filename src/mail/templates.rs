@@ -0,0 +1,168 @@
+//! src/mail/templates.rs
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use tera::Tera;
+
+use crate::config::EmailTemplateSettings;
+use crate::domain::subscriber_locale::SubscriberLocale;
+
+/// Every transactional email we send, each needs a variant in the default locale.
+pub const TEMPLATE_NAMES: [&str; 3] = ["confirmation", "password_reset", "unsubscribe_ack"];
+
+/// The three files making up one email: `<name>.subject.txt`, `<name>.html`
+/// and `<name>.txt`. Only the `.html` one is auto-escaped, the links we build
+/// ourselves are marked `| safe` in there.
+const PARTS: [&str; 3] = ["subject.txt", "html", "txt"];
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Transactional email templates, read from `<directory>/<locale>/` and compiled
+/// once at startup so that a broken template stops the deploy rather than an email.
+pub struct EmailTemplates {
+    tera: Tera,
+    default_locale: String,
+}
+
+impl EmailTemplates {
+    pub fn load(settings: &EmailTemplateSettings) -> Result<Self, anyhow::Error> {
+        let glob = format!("{}/**/*", settings.directory.trim_end_matches('/'));
+        let tera = Tera::new(&glob)
+            .with_context(|| format!("Failed to compile the email templates in {}", glob))?;
+        let templates = Self {
+            tera,
+            default_locale: settings.default_locale.clone(),
+        };
+        for name in TEMPLATE_NAMES {
+            if !templates.has_variant(&templates.default_locale, name) {
+                anyhow::bail!(
+                    "The `{}` email is missing from the default locale ({}).",
+                    name,
+                    templates.default_locale
+                );
+            }
+        }
+        Ok(templates)
+    }
+
+    /// Picks the subscriber's locale, then its bare language, then the default.
+    /// A locale only counts if it has all three parts of the email.
+    pub fn render(
+        &self,
+        name: &str,
+        locale: Option<&SubscriberLocale>,
+        context: &tera::Context,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let locale = locale
+            .into_iter()
+            .flat_map(|locale| locale.fallbacks())
+            .find(|locale| self.has_variant(locale, name))
+            .unwrap_or(&self.default_locale);
+        let render = |part: &str| {
+            let template = format!("{}/{}.{}", locale, name, part);
+            self.tera
+                .render(&template, context)
+                .with_context(|| format!("Failed to render the {} email template", template))
+        };
+        Ok(RenderedEmail {
+            subject: render("subject.txt")?.trim().to_string(),
+            html: render("html")?,
+            text: render("txt")?,
+        })
+    }
+
+    fn has_variant(&self, locale: &str, name: &str) -> bool {
+        let names: HashSet<&str> = self.tera.get_template_names().collect();
+        PARTS
+            .iter()
+            .all(|part| names.contains(format!("{}/{}.{}", locale, name, part).as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+
+    use crate::config::EmailTemplateSettings;
+    use crate::domain::subscriber_locale::SubscriberLocale;
+
+    use super::EmailTemplates;
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(&EmailTemplateSettings {
+            directory: "templates/email".into(),
+            default_locale: "en".into(),
+        })
+        .unwrap()
+    }
+
+    fn context(name: &str) -> tera::Context {
+        let mut context = tera::Context::new();
+        context.insert("name", name);
+        context.insert("confirmation_link", "https://example.com/confirm?a=1&b=2");
+        context
+    }
+
+    fn locale(s: &str) -> SubscriberLocale {
+        SubscriberLocale::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn the_html_part_is_escaped_and_the_text_part_is_not() {
+        let email = assert_ok!(templates().render("confirmation", None, &context("Tom & Jerry")));
+        assert!(email.html.contains("Tom &amp; Jerry"));
+        assert!(email.text.contains("Tom & Jerry"));
+        assert!(email.text.contains("https://example.com/confirm?a=1&b=2"));
+    }
+
+    #[test]
+    fn links_are_left_as_they_are_in_the_html_part() {
+        let templates = templates();
+        for locale in [None, Some(locale("fr"))] {
+            let email =
+                assert_ok!(templates.render("confirmation", locale.as_ref(), &context("Ursula")));
+            let href = email
+                .html
+                .split("href=\"")
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .unwrap();
+            let link = assert_ok!(reqwest::Url::parse(href));
+            assert_eq!(link.as_str(), "https://example.com/confirm?a=1&b=2");
+        }
+    }
+
+    #[test]
+    fn the_subscriber_locale_is_used_when_there_is_a_variant() {
+        let templates = templates();
+        let english = templates
+            .render("confirmation", None, &context("Ursula"))
+            .unwrap();
+        let french = templates
+            .render("confirmation", Some(&locale("fr")), &context("Ursula"))
+            .unwrap();
+        let canadian_french = templates
+            .render("confirmation", Some(&locale("fr-CA")), &context("Ursula"))
+            .unwrap();
+        let german = templates
+            .render("confirmation", Some(&locale("de")), &context("Ursula"))
+            .unwrap();
+        assert_ne!(french.subject, english.subject);
+        assert_eq!(canadian_french.subject, french.subject);
+        assert_eq!(german.subject, english.subject);
+    }
+
+    #[test]
+    fn a_default_locale_without_every_email_is_rejected() {
+        assert!(EmailTemplates::load(&EmailTemplateSettings {
+            directory: "templates/email".into(),
+            default_locale: "xx".into(),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! src/mail/transport.rs
+
+use crate::domain::subscriber_email::SubscriberEmail;
+
+pub struct EmailMessage<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    /// Extra headers, e.g. `List-Unsubscribe`, on top of the ones every transport sets.
+    pub headers: Vec<(&'static str, String)>,
+}
+
+/// Everything that needs to send an email depends on this trait rather than on a
+/// concrete provider, which one is used is decided by `EmailClientSettings::transport`.
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send(&EmailMessage {
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers: vec![],
+        })
+        .await
+    }
+}
@@ -1,9 +1,14 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 use zero2prod::config::get_configuration;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 use zero2prod::startup::AppServer;
+use zero2prod::subscription_sweeper::run_sweeper_until_stopped;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     init_subscriber(get_subscriber(
         "zero2prod".into(),
         "info".into(),
@@ -11,11 +16,43 @@ async fn main() -> std::io::Result<()> {
     ));
 
     let configuration = get_configuration().expect("Should have loaded configuration");
-    let server = AppServer::build(configuration)
-        .await
-        .expect("should have created server");
+    let server = AppServer::build(configuration.clone()).await?;
+
+    let server_task = tokio::spawn(server.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let sweeper_task = tokio::spawn(run_sweeper_until_stopped(configuration));
 
-    server.run_until_stopped().await?;
+    // Whichever task exits first takes the whole process down with it, we do not
+    // want to keep accepting newsletters that nobody will ever deliver (or vice-versa).
+    tokio::select! {
+        outcome = server_task => report_exit("API", outcome),
+        outcome = worker_task => report_exit("Background worker", outcome),
+        outcome = sweeper_task => report_exit("Subscription sweeper", outcome),
+    };
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
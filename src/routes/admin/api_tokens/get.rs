@@ -0,0 +1,94 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+
+use crate::authentication::api_token::get_active_api_tokens;
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::middleware::UserId;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+pub async fn api_tokens(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for token in get_active_api_tokens(*user_id, &pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{name}</td>
+            <td>{scope}</td>
+            <td>{created_at}</td>
+            <td>{last_used_at}</td>
+            <td>
+                <form action="/admin/api-tokens/revoke" method="post">
+                    {csrf_field}
+                    <input hidden type="text" name="token_id" value="{token_id}">
+                    <button type="submit">Revoke</button>
+                </form>
+            </td>
+        </tr>"#,
+            name = encode_minimal(&token.name),
+            scope = token.scope,
+            created_at = token.created_at.to_rfc3339(),
+            last_used_at = token
+                .last_used_at
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| "Never".into()),
+            token_id = token.token_id,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Scope</th>
+            <th>Created at</th>
+            <th>Last used at</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/api-tokens" method="post">
+        {csrf_field}
+        <label>Name
+            <input
+                type="text"
+                placeholder="What is this token for?"
+                name="name"
+            >
+        </label>
+        <button type="submit">Create a token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
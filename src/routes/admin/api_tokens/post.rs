@@ -0,0 +1,84 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use htmlescape::encode_minimal;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::api_token::{create_api_token, revoke_api_token, ApiTokenScope};
+use crate::authentication::middleware::UserId;
+use crate::utils::middleware::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct CreateFormData {
+    name: String,
+}
+
+/// The token is rendered straight into the response rather than through a flash
+/// message: flash cookies are signed, not encrypted.
+#[tracing::instrument(name = "Create an API token", skip(form, pool, user_id))]
+pub async fn create_token(
+    form: web::Form<CreateFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let name = form.0.name.trim().to_string();
+    if name.is_empty() {
+        FlashMessage::error("Please give the token a name.").send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+
+    let token = create_api_token(*user_id, &name, ApiTokenScope::PublishNewsletters, &pool)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API token created</title>
+</head>
+<body>
+    <p>Your new token <b>{name}</b> can publish newsletter issues.</p>
+    <p>Copy it now, it will not be shown again:</p>
+    <pre><code id="api-token">{token}</code></pre>
+    <p><a href="/admin/api-tokens">&lt;- Back</a></p>
+</body>
+</html>"#,
+            name = encode_minimal(&name),
+            token = token.expose_secret(),
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RevokeFormData {
+    token_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(form, pool, user_id),
+    fields(token_id = %form.token_id)
+)]
+pub async fn revoke_token(
+    form: web::Form<RevokeFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if revoke_api_token(*user_id, form.token_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("The API token could not be found, it may have been revoked already.")
+            .send();
+    }
+    Ok(see_other("/admin/api-tokens"))
+}
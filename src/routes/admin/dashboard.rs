@@ -1,4 +1,3 @@
-use actix_session::Session;
 use actix_web::http::header::ContentType;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
@@ -6,6 +5,8 @@ use reqwest::header::LOCATION;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::role::Role;
 use crate::session_state::TypedSession;
 
 fn e500<T>(e: T) -> actix_web::Error
@@ -19,7 +20,9 @@ where
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let role = role.into_inner();
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
         get_username(user_id, &pool).await.map_err(e500)?
     } else {
@@ -27,6 +30,7 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
+    let csrf_field = csrf_field(&session).map_err(e500)?;
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -38,19 +42,32 @@ pub async fn admin_dashboard(
     <title>Admin dashboard</title>
 </head>
 <body>
-    <p>Welcome {}!</p>
+    <p>Welcome {}! You are signed in as {role}.</p>
     <p>Available actions:</p>
     <ol>
+        <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
+        <li><a href="/admin/subscribers">Subscribers</a></li>
         <li><a href="/admin/password">Change password</a></li>
+        <li><a href="/admin/2fa">Two-factor authentication</a></li>
+        <li><a href="/admin/sessions">Active sessions</a></li>
+        <li><a href="/admin/api-tokens">API tokens</a></li>
+        {users_link}
+        <li><a href="/admin/deliveries/failed">Failed deliveries</a></li>
         <li>
           <form name="logoutForm" action="/admin/logout" method="post">
+            {csrf_field}
             <input type="submit" value="Logout">
           </form>
         </li>
     </ol>
 </body>
 </html>"#,
-            username
+            username,
+            users_link = if role == Role::Owner {
+                r#"<li><a href="/admin/users">Users</a></li>"#
+            } else {
+                ""
+            },
         )))
 }
 
@@ -0,0 +1,116 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::csrf::csrf_field;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i32,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+pub async fn failed_deliveries(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for dead_letter in get_dead_letters(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{title}</td>
+            <td>{email}</td>
+            <td>{n_retries}</td>
+            <td>{failed_at}</td>
+            <td>{last_error}</td>
+            <td>
+                <form action="/admin/deliveries/failed/requeue" method="post">
+                    {csrf_field}
+                    <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                    <input hidden type="text" name="subscriber_email" value="{email}">
+                    <button type="submit">Requeue</button>
+                </form>
+            </td>
+        </tr>"#,
+            title = encode_minimal(&dead_letter.title),
+            email = encode_minimal(&dead_letter.subscriber_email),
+            n_retries = dead_letter.n_retries,
+            failed_at = dead_letter.failed_at.to_rfc3339(),
+            last_error = encode_minimal(&dead_letter.last_error),
+            issue_id = dead_letter.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Failed deliveries</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Issue</th>
+            <th>Subscriber</th>
+            <th>Attempts</th>
+            <th>Failed at</th>
+            <th>Last error</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Get dead-lettered deliveries", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_retries,
+            d.last_error,
+            d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY d.failed_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead-lettered deliveries.")?;
+
+    Ok(rows)
+}
@@ -0,0 +1,99 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::middleware::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(
+    name = "Requeue a dead-lettered delivery",
+    skip(form, pool),
+    fields(
+        newsletter_issue_id = %form.newsletter_issue_id,
+        subscriber_email = %form.subscriber_email,
+    )
+)]
+pub async fn requeue_failed_delivery(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue_dead_letter(&pool, form.newsletter_issue_id, &form.subscriber_email)
+        .await
+        .map_err(e500)?;
+
+    if requeued {
+        FlashMessage::info(format!(
+            "The delivery to {} has been requeued.",
+            form.subscriber_email
+        ))
+        .send();
+    } else {
+        FlashMessage::error("The delivery could not be found, it may have been requeued already.")
+            .send();
+    }
+    Ok(see_other("/admin/deliveries/failed"))
+}
+
+async fn requeue_dead_letter(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_dead_letters
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove the dead-lettered delivery.")?
+    .rows_affected();
+
+    if n_deleted == 0 {
+        return Ok(false);
+    }
+
+    // The retry budget starts over, an admin requeueing a task usually means
+    // whatever was causing it to fail has been fixed.
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            execute_after
+        )
+        VALUES ($1, $2, 0, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enqueue the delivery task.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to requeue a delivery.")?;
+
+    Ok(true)
+}
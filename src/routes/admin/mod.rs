@@ -1,2 +1,9 @@
+pub mod api_tokens;
 pub mod dashboard;
+pub mod deliveries;
+pub mod newsletters;
 pub mod password;
+pub mod sessions;
+pub mod subscribers;
+pub mod two_factor;
+pub mod users;
@@ -0,0 +1,151 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::csrf::csrf_field;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    send_at: DateTime<Utc>,
+}
+
+pub async fn publish_newsletter_form(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+    // A fresh key for every rendering of the form, resubmitting the same form
+    // (double clicks, browser retries) reuses it and does not publish twice.
+    let idempotency_key = uuid::Uuid::new_v4();
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+
+    let mut scheduled_html = String::new();
+    for issue in get_scheduled_issues(&pool).await.map_err(e500)? {
+        writeln!(
+            scheduled_html,
+            r#"<tr>
+            <td>{title}</td>
+            <td>{send_at}</td>
+            <td>
+                <form action="/admin/newsletters/scheduled/reschedule" method="post">
+                    {csrf_field}
+                    <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                    <input type="datetime-local" name="send_at" required>
+                    <input type="text" name="timezone" value="UTC" size="15">
+                    <button type="submit">Reschedule</button>
+                </form>
+                <form action="/admin/newsletters/scheduled/cancel" method="post">
+                    {csrf_field}
+                    <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                    <button type="submit">Cancel</button>
+                </form>
+            </td>
+        </tr>"#,
+            title = encode_minimal(&issue.title),
+            send_at = issue.send_at.to_rfc3339(),
+            issue_id = issue.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+    let scheduled_html = if scheduled_html.is_empty() {
+        "<p>No issue is scheduled.</p>".to_string()
+    } else {
+        format!(
+            r#"<table>
+        <tr><th>Title</th><th>Sending at (UTC)</th><th></th></tr>
+        {scheduled_html}
+    </table>"#
+        )
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Publish Newsletter Issue</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/newsletters" method="post">
+        {csrf_field}
+        <label>Title:<br>
+            <input
+                type="text"
+                placeholder="Enter the issue title"
+                name="title"
+            >
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea
+                placeholder="Enter the content in plain text"
+                name="text_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea
+                placeholder="Enter the content in HTML format"
+                name="html_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <p>
+            The title and content can use <code>{{{{ name }}}}</code>,
+            <code>{{{{ unsubscribe_url }}}}</code> and <code>{{{{ confirmed_at }}}}</code>,
+            they are filled in for every subscriber.
+        </p>
+        <label>Send at (leave empty to send now):<br>
+            <input type="datetime-local" name="send_at">
+        </label>
+        <label>Time zone:
+            <input type="text" name="timezone" value="UTC" placeholder="Europe/Paris">
+        </label>
+        <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Publish</button>
+    </form>
+    <h2>Scheduled issues</h2>
+    {scheduled_html}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, send_at AS "send_at!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY send_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the scheduled newsletter issues")?;
+    Ok(issues)
+}
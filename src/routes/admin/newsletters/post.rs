@@ -0,0 +1,214 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::domain::send_at::SendAt;
+use crate::idempotency::key::IdempotencyKey;
+use crate::idempotency::persistence::{save_response, try_processing, NextAction};
+use crate::mail::placeholders::validate_placeholders;
+use crate::routes::newsletter::{enqueue_delivery_tasks, insert_newsletter_issue};
+use crate::utils::middleware::{e400, e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+    /// A `datetime-local` value, empty to publish right away.
+    send_at: Option<String>,
+    timezone: Option<String>,
+}
+
+const INVALID_SEND_AT: &str =
+    "The scheduled time must be a valid date in the future, in a known time zone \
+        such as Europe/Paris.";
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin panel",
+    skip(form, pool, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn publish_newsletter_issue(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let FormData {
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+        send_at,
+        timezone,
+    } = form.0;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+
+    if title.trim().is_empty() || (text_content.trim().is_empty() && html_content.trim().is_empty())
+    {
+        FlashMessage::error("A newsletter issue needs a title and some content.").send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    if [&title, &text_content, &html_content]
+        .iter()
+        .any(|part| validate_placeholders(part).is_err())
+    {
+        FlashMessage::error(
+            "Only the {{ name }}, {{ unsubscribe_url }} and {{ confirmed_at }} placeholders \
+                can be used.",
+        )
+        .send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    let send_at = match send_at.filter(|send_at| !send_at.trim().is_empty()) {
+        Some(send_at) => {
+            match SendAt::parse_local(&send_at, timezone.as_deref().unwrap_or("UTC")) {
+                Ok(send_at) => Some(send_at),
+                Err(_) => {
+                    FlashMessage::error(INVALID_SEND_AT).send();
+                    return Ok(see_other("/admin/newsletters"));
+                }
+            }
+        }
+        None => None,
+    };
+    let success_message = success_message(send_at.is_some());
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message.send();
+            return Ok(saved_response);
+        }
+        NextAction::Conflict => {
+            FlashMessage::error("This newsletter issue is already being published.").send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        send_at,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
+
+    if send_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")
+            .map_err(e500)?;
+    }
+
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message.send();
+    Ok(response)
+}
+
+fn success_message(scheduled: bool) -> FlashMessage {
+    if scheduled {
+        FlashMessage::info("The newsletter issue has been scheduled.")
+    } else {
+        FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduledIssueFormData {
+    newsletter_issue_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip(form, pool),
+    fields(newsletter_issue_id = %form.newsletter_issue_id)
+)]
+pub async fn cancel_scheduled_issue(
+    form: web::Form<ScheduledIssueFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        form.newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to cancel a scheduled newsletter issue")
+    .map_err(e500)?;
+
+    if result.rows_affected() == 1 {
+        FlashMessage::info("The scheduled newsletter issue has been cancelled.").send();
+    } else {
+        not_scheduled_message().send();
+    }
+    Ok(see_other("/admin/newsletters"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleFormData {
+    newsletter_issue_id: Uuid,
+    send_at: String,
+    timezone: String,
+}
+
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip(form, pool),
+    fields(newsletter_issue_id = %form.newsletter_issue_id)
+)]
+pub async fn reschedule_issue(
+    form: web::Form<RescheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let send_at = match SendAt::parse_local(&form.send_at, &form.timezone) {
+        Ok(send_at) => send_at,
+        Err(_) => {
+            FlashMessage::error(INVALID_SEND_AT).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+    // The worker locks due issues while releasing them, this waits for it and
+    // then finds the issue published.
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        form.newsletter_issue_id,
+        send_at.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule a newsletter issue")
+    .map_err(e500)?;
+
+    if result.rows_affected() == 1 {
+        FlashMessage::info("The newsletter issue has been rescheduled.").send();
+    } else {
+        not_scheduled_message().send();
+    }
+    Ok(see_other("/admin/newsletters"))
+}
+
+fn not_scheduled_message() -> FlashMessage {
+    FlashMessage::error("This newsletter issue is no longer scheduled, it may already be out.")
+}
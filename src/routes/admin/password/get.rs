@@ -3,7 +3,9 @@ use std::fmt::Write;
 use actix_web::http::header::ContentType;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
 
+use crate::authentication::csrf::csrf_field;
 use crate::session_state::TypedSession;
 use crate::utils::middleware::{e500, see_other};
 
@@ -14,9 +16,10 @@ pub async fn change_password_form(
     if session.get_user_id().map_err(e500)?.is_none() {
         return Ok(see_other("/login"));
     };
+    let csrf_field = csrf_field(&session).map_err(e500)?;
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
     }
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -30,6 +33,7 @@ pub async fn change_password_form(
 <body>
     {msg_html}
     <form action="/admin/password" method="post">
+        {csrf_field}
         <label>Current password
             <input
                 type="password"
@@ -5,7 +5,11 @@ use sqlx::PgPool;
 
 use crate::authentication::auth::{change_password, validate_credentials, AuthError, Credentials};
 use crate::authentication::middleware::UserId;
+use crate::authentication::password_policy::validate_new_password;
+use crate::authentication::session_registry::SessionRegistry;
+use crate::config::PasswordHashingSettings;
 use crate::routes::admin::dashboard::get_username;
+use crate::session_state::TypedSession;
 use crate::utils::middleware::{e500, see_other};
 
 #[derive(serde::Deserialize)]
@@ -18,7 +22,10 @@ pub struct FormData {
 pub async fn change_password_endpoint(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
     user_id: web::ReqData<UserId>, // extract request data from the request extensions using `ReqData`
+    session: TypedSession,
+    registry: web::Data<SessionRegistry>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
@@ -28,12 +35,16 @@ pub async fn change_password_endpoint(
         .send();
         return Ok(see_other("/admin/password"));
     }
+    if let Err(e) = validate_new_password(&form.new_password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other("/admin/password"));
+    }
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
     let credentials = Credentials {
         username,
         password: form.0.current_password,
     };
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &hashing, &pool).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
@@ -42,9 +53,19 @@ pub async fn change_password_endpoint(
             AuthError::UnexpectedError(_) => Err(e500(e)),
         };
     }
-    change_password(*user_id, form.0.new_password, &pool)
+    let session_generation =
+        change_password(*user_id, form.0.new_password, &hashing, pool.get_ref())
+            .await
+            .map_err(e500)?;
+    // Every other session is now stale, this one carries on under a fresh id.
+    session.renew();
+    session
+        .insert_session_generation(session_generation)
+        .map_err(e500)?;
+    registry
+        .revoke_all_except(*user_id, session.get_session_id().map_err(e500)?)
         .await
         .map_err(e500)?;
-    FlashMessage::error("Your password has been changed.").send();
+    FlashMessage::info("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
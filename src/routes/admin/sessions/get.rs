@@ -0,0 +1,89 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::{TimeZone, Utc};
+use htmlescape::encode_minimal;
+
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::middleware::UserId;
+use crate::authentication::session_registry::SessionRegistry;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+pub async fn list_sessions(
+    registry: web::Data<SessionRegistry>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for record in registry.list(*user_id).await.map_err(e500)? {
+        let action = if Some(record.session_id) == current_session_id {
+            "This session".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/revoke" method="post">
+                    {csrf_field}
+                    <input hidden type="text" name="session_id" value="{}">
+                    <button type="submit">Sign out</button>
+                </form>"#,
+                record.session_id
+            )
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{created_at}</td>
+            <td>{last_seen_at}</td>
+            <td>{ip_address}</td>
+            <td>{user_agent}</td>
+            <td>{action}</td>
+        </tr>"#,
+            created_at = Utc.timestamp(record.created_at, 0).to_rfc3339(),
+            last_seen_at = Utc.timestamp(record.last_seen_at, 0).to_rfc3339(),
+            ip_address = encode_minimal(record.ip_address.as_deref().unwrap_or("Unknown")),
+            user_agent = encode_minimal(record.user_agent.as_deref().unwrap_or("Unknown")),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Active sessions</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Signed in at</th>
+            <th>Last seen at</th>
+            <th>IP address</th>
+            <th>Browser</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/sessions/sign-out-others" method="post">
+        {csrf_field}
+        <button type="submit">Sign out everywhere else</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
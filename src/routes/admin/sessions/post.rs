@@ -0,0 +1,49 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::authentication::session_registry::SessionRegistry;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct RevokeFormData {
+    session_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Sign out a session",
+    skip(form, registry, user_id),
+    fields(session_id = %form.session_id)
+)]
+pub async fn revoke_session(
+    form: web::Form<RevokeFormData>,
+    registry: web::Data<SessionRegistry>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // Only ever looks into the registry of the current user.
+    registry
+        .revoke(*user_id, form.session_id)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The session has been signed out.").send();
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(name = "Sign out other sessions", skip(registry, session, user_id))]
+pub async fn sign_out_other_sessions(
+    registry: web::Data<SessionRegistry>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let n_revoked = registry
+        .revoke_all_except(*user_id, current_session_id)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!("Signed out of {} other session(s).", n_revoked)).send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,132 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::routes::admin::subscribers::get::Filters;
+use crate::utils::middleware::e400;
+
+/// How many encoded rows may wait for a slow client before the query pauses.
+const BUFFERED_ROWS: usize = 256;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportParameters {
+    status: Option<String>,
+    q: Option<String>,
+    signed_up_from: Option<String>,
+    signed_up_to: Option<String>,
+}
+
+struct ExportRow {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+/// Streams the subscribers matching the same filters as the list page. Rows
+/// are encoded as they come off the cursor, the table is never held in memory.
+#[tracing::instrument(name = "Export subscribers", skip(parameters, pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ExportParameters {
+        status,
+        q,
+        signed_up_from,
+        signed_up_to,
+    } = parameters.into_inner();
+    let filters = Filters::parse(status, q, signed_up_from, signed_up_to).map_err(e400)?;
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_ROWS);
+    let pool = pool.get_ref().clone();
+    tokio::spawn(
+        async move {
+            if let Err(e) = write_rows(&pool, &filters, &sender).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to export subscribers"
+                );
+                // Ends the response with an error rather than a truncated file.
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .in_current_span(),
+    );
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming(body))
+}
+
+type Chunk = Result<web::Bytes, anyhow::Error>;
+
+async fn write_rows(
+    pool: &PgPool,
+    filters: &Filters,
+    sender: &mpsc::Sender<Chunk>,
+) -> Result<(), anyhow::Error> {
+    let header = csv_row(["email", "name", "status", "subscribed_at"])?;
+    if sender.send(Ok(header)).await.is_err() {
+        return Ok(());
+    }
+
+    let mut rows = sqlx::query_as!(
+        ExportRow,
+        r#"
+        SELECT email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR status = $1) AND
+            ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2) AND
+            ($3::timestamptz IS NULL OR subscribed_at >= $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at < $4)
+        ORDER BY subscribed_at DESC, id DESC
+        "#,
+        filters.status,
+        filters.search_pattern(),
+        filters.signed_up_from_bound(),
+        filters.signed_up_to_bound(),
+    )
+    .fetch(pool);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("Failed to retrieve subscribers to export.")?
+    {
+        let chunk = csv_row([
+            row.email.as_str(),
+            row.name.as_str(),
+            row.status.as_str(),
+            row.subscribed_at.to_rfc3339().as_str(),
+        ])?;
+        if sender.send(Ok(chunk)).await.is_err() {
+            // The client went away, there is no one left to write to.
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// A single CSV line, quoted and escaped as needed.
+fn csv_row(record: [&str; 4]) -> Result<web::Bytes, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(record)?;
+    let line = writer
+        .into_inner()
+        .map_err(|e| anyhow::anyhow!("Failed to write a CSV row: {}", e))?;
+    Ok(web::Bytes::from(line))
+}
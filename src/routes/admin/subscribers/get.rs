@@ -0,0 +1,533 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::role::Role;
+use crate::session_state::TypedSession;
+use crate::subscription_history::get_subscription_history;
+use crate::utils::middleware::{e400, e500};
+
+const PAGE_SIZE: i64 = 50;
+pub const STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ListParameters {
+    status: Option<String>,
+    q: Option<String>,
+    /// Inclusive bounds on the signup date, `YYYY-MM-DD`.
+    signed_up_from: Option<String>,
+    signed_up_to: Option<String>,
+    /// Where the previous page ended, see `Cursor`.
+    after: Option<String>,
+}
+
+/// Position of a row in the list, which is sorted by `(subscribed_at, id)`,
+/// newest first. Pages start right after the cursor, so rows coming and going
+/// in between do not shift them the way an offset would.
+#[derive(Debug, PartialEq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let micros = self.subscribed_at.timestamp() * 1_000_000
+            + self.subscribed_at.timestamp_subsec_micros() as i64;
+        format!("{}_{}", micros, self.id)
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("{} is not a valid page cursor.", s);
+        let (micros, id) = s.split_once('_').ok_or_else(invalid)?;
+        let micros: i64 = micros.parse().map_err(|_| invalid())?;
+        let subscribed_at = Utc
+            .timestamp_opt(
+                micros.div_euclid(1_000_000),
+                (micros.rem_euclid(1_000_000) * 1_000) as u32,
+            )
+            .single()
+            .ok_or_else(invalid)?;
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+        Ok(Self { subscribed_at, id })
+    }
+}
+
+/// Validated `ListParameters`, empty form fields count as not set.
+#[derive(Debug, Default)]
+pub struct Filters {
+    pub status: Option<String>,
+    pub search: Option<String>,
+    pub signed_up_from: Option<NaiveDate>,
+    pub signed_up_to: Option<NaiveDate>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_date(value: Option<String>) -> Result<Option<NaiveDate>, String> {
+    non_empty(value)
+        .map(|v| {
+            NaiveDate::parse_from_str(&v, "%Y-%m-%d")
+                .map_err(|_| format!("{} is not a valid date, use YYYY-MM-DD.", v))
+        })
+        .transpose()
+}
+
+impl Filters {
+    pub fn parse(
+        status: Option<String>,
+        search: Option<String>,
+        signed_up_from: Option<String>,
+        signed_up_to: Option<String>,
+    ) -> Result<Self, String> {
+        let status = non_empty(status);
+        if let Some(status) = &status {
+            if !STATUSES.contains(&status.as_str()) {
+                return Err(format!("{} is not a subscription status.", status));
+            }
+        }
+        Ok(Self {
+            status,
+            search: non_empty(search),
+            signed_up_from: parse_date(signed_up_from)?,
+            signed_up_to: parse_date(signed_up_to)?,
+        })
+    }
+
+    /// The filters as query string pairs, for links that keep them.
+    pub fn query_string(&self) -> String {
+        let mut pairs = Vec::new();
+        if let Some(status) = &self.status {
+            pairs.push(format!("status={}", urlencoding::encode(status)));
+        }
+        if let Some(search) = &self.search {
+            pairs.push(format!("q={}", urlencoding::encode(search)));
+        }
+        if let Some(from) = self.signed_up_from {
+            pairs.push(format!("signed_up_from={}", from.format("%Y-%m-%d")));
+        }
+        if let Some(to) = self.signed_up_to {
+            pairs.push(format!("signed_up_to={}", to.format("%Y-%m-%d")));
+        }
+        pairs.join("&")
+    }
+
+    pub(crate) fn search_pattern(&self) -> Option<String> {
+        self.search.as_ref().map(|search| {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+
+    pub(crate) fn signed_up_from_bound(&self) -> Option<DateTime<Utc>> {
+        self.signed_up_from
+            .map(|date| Utc.from_utc_datetime(&date.and_hms(0, 0, 0)))
+    }
+
+    /// Exclusive, the day after `signed_up_to`.
+    pub(crate) fn signed_up_to_bound(&self) -> Option<DateTime<Utc>> {
+        self.signed_up_to
+            .map(|date| Utc.from_utc_datetime(&date.and_hms(0, 0, 0)) + Duration::days(1))
+    }
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ListParameters {
+        status,
+        q,
+        signed_up_from,
+        signed_up_to,
+        after,
+    } = parameters.into_inner();
+    let filters = Filters::parse(status, q, signed_up_from, signed_up_to).map_err(e400)?;
+    let after = non_empty(after)
+        .map(|after| Cursor::parse(&after))
+        .transpose()
+        .map_err(e400)?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let mut subscribers = get_subscribers_page(&pool, &filters, after.as_ref())
+        .await
+        .map_err(e500)?;
+    let next_page = if subscribers.len() as i64 > PAGE_SIZE {
+        subscribers.truncate(PAGE_SIZE as usize);
+        subscribers.last().map(|last| Cursor {
+            subscribed_at: last.subscribed_at,
+            id: last.id,
+        })
+    } else {
+        None
+    };
+
+    let mut rows_html = String::new();
+    for subscriber in &subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td><a href="/admin/subscribers/{id}">{email}</a></td>
+            <td>{name}</td>
+            <td>{status}</td>
+            <td>{subscribed_at}</td>
+        </tr>"#,
+            id = subscriber.id,
+            email = encode_minimal(&subscriber.email),
+            name = encode_minimal(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let filters_query = filters.query_string();
+    let mut pagination_html = String::new();
+    if after.is_some() {
+        writeln!(
+            pagination_html,
+            r#"<a href="/admin/subscribers?{}">First page</a>"#,
+            encode_minimal(&filters_query)
+        )
+        .unwrap();
+    }
+    if let Some(next_page) = next_page {
+        let separator = if filters_query.is_empty() { "" } else { "&" };
+        writeln!(
+            pagination_html,
+            r#"<a href="/admin/subscribers?{}">Next page -&gt;</a>"#,
+            encode_minimal(&format!(
+                "{}{}after={}",
+                filters_query,
+                separator,
+                next_page.encode()
+            ))
+        )
+        .unwrap();
+    }
+
+    let mut status_options = String::from(r#"<option value="">Any</option>"#);
+    for status in STATUSES {
+        let selected = if filters.status.as_deref() == Some(status) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            status_options,
+            r#"<option value="{status}"{selected}>{status}</option>"#
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/subscribers" method="get">
+        <label>Search
+            <input type="search" placeholder="Email or name" name="q" value="{search}">
+        </label>
+        <label>Status
+            <select name="status">{status_options}</select>
+        </label>
+        <label>Signed up from
+            <input type="date" name="signed_up_from" value="{signed_up_from}">
+        </label>
+        <label>to
+            <input type="date" name="signed_up_to" value="{signed_up_to}">
+        </label>
+        <button type="submit">Filter</button>
+    </form>
+    <table>
+        <tr>
+            <th>Email</th>
+            <th>Name</th>
+            <th>Status</th>
+            <th>Signed up at</th>
+        </tr>
+        {rows_html}
+    </table>
+    <p>{pagination_html}</p>
+    <p>
+        <a href="/admin/subscribers/export?{export_query}">Export these subscribers as CSV</a>
+        <a href="/admin/subscribers/import">Import from CSV</a>
+    </p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            export_query = encode_minimal(&filters_query),
+            search = encode_minimal(filters.search.as_deref().unwrap_or("")),
+            signed_up_from = filters
+                .signed_up_from
+                .map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            signed_up_to = filters
+                .signed_up_to
+                .map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+        )))
+}
+
+/// Fetches one row more than a page holds, to know whether there is a next one.
+#[tracing::instrument(name = "Get a page of subscribers", skip(pool))]
+async fn get_subscribers_page(
+    pool: &PgPool,
+    filters: &Filters,
+    after: Option<&Cursor>,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR status = $1) AND
+            ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2) AND
+            ($3::timestamptz IS NULL OR subscribed_at >= $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at < $4) AND
+            ($5::timestamptz IS NULL OR (subscribed_at, id) < ($5, $6::uuid))
+        ORDER BY subscribed_at DESC, id DESC
+        LIMIT $7
+        "#,
+        filters.status,
+        filters.search_pattern(),
+        filters.signed_up_from_bound(),
+        filters.signed_up_to_bound(),
+        after.map(|cursor| cursor.subscribed_at),
+        after.map(|cursor| cursor.id),
+        PAGE_SIZE + 1
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscribers.")?;
+    Ok(rows)
+}
+
+pub(crate) struct SubscriberDetails {
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let role = role.into_inner();
+    let subscriber = match get_subscriber(&pool, subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => {
+            return Ok(HttpResponse::NotFound()
+                .content_type(ContentType::html())
+                .body(
+                    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    <p>There is no such subscriber.</p>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+                ))
+        }
+    };
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let mut history_html = String::new();
+    for entry in get_subscription_history(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        writeln!(
+            history_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            entry.occurred_at.to_rfc3339(),
+            entry.event,
+            encode_minimal(entry.actor.as_deref().unwrap_or("Subscriber")),
+        )
+        .unwrap();
+    }
+
+    let mut actions_html = String::new();
+    if role >= Role::Editor {
+        let csrf_field = csrf_field(&session).map_err(e500)?;
+        let mut action = |path: &str, label: &str| {
+            writeln!(
+                actions_html,
+                r#"<form action="/admin/subscribers/{path}" method="post">
+        {csrf_field}
+        <input hidden type="text" name="subscriber_id" value="{subscriber_id}">
+        <button type="submit">{label}</button>
+    </form>"#,
+            )
+            .unwrap();
+        };
+        if subscriber.status == "pending_confirmation" {
+            action("resend-confirmation", "Resend confirmation email");
+        }
+        if subscriber.status != "unsubscribed" {
+            action("unsubscribe", "Unsubscribe");
+        }
+        if role == Role::Owner {
+            action("delete", "Delete permanently");
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <dl>
+        <dt>Email</dt><dd>{email}</dd>
+        <dt>Name</dt><dd>{name}</dd>
+        <dt>Status</dt><dd>{status}</dd>
+        <dt>Signed up at</dt><dd>{subscribed_at}</dd>
+    </dl>
+    {actions_html}
+    <h2>History</h2>
+    <table>
+        <tr>
+            <th>When</th>
+            <th>What</th>
+            <th>By</th>
+        </tr>
+        {history_html}
+    </table>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            email = encode_minimal(&subscriber.email),
+            name = encode_minimal(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+        )))
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+pub(crate) async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberDetails,
+        r#"
+        SELECT email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a subscriber.")?;
+    Ok(subscriber)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    use super::{Cursor, Filters};
+
+    #[test]
+    fn cursors_round_trip_with_microsecond_precision() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp(1_660_000_000, 123_456_000),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(Cursor::parse(&cursor.encode()), Ok(cursor));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        assert_err!(Cursor::parse("garbage"));
+        assert_err!(Cursor::parse("12_not-a-uuid"));
+        assert_err!(Cursor::parse(&format!("abc_{}", Uuid::new_v4())));
+    }
+
+    #[test]
+    fn empty_filters_are_ignored() {
+        let filters = assert_ok!(Filters::parse(
+            Some("".into()),
+            Some("  ".into()),
+            Some("".into()),
+            None
+        ));
+        assert!(filters.status.is_none());
+        assert!(filters.search.is_none());
+        assert!(filters.signed_up_from.is_none());
+        assert_eq!(filters.query_string(), "");
+    }
+
+    #[test]
+    fn unknown_statuses_and_malformed_dates_are_rejected() {
+        assert_err!(Filters::parse(Some("banned".into()), None, None, None));
+        assert_err!(Filters::parse(None, None, Some("01/02/2022".into()), None));
+    }
+
+    #[test]
+    fn like_wildcards_in_searches_are_escaped() {
+        let filters = assert_ok!(Filters::parse(None, Some("50%_off".into()), None, None));
+        assert_eq!(filters.search_pattern().as_deref(), Some("%50\\%\\_off%"));
+    }
+}
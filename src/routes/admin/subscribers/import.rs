@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use actix_multipart::Multipart;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use htmlescape::encode_minimal;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::authentication::csrf::{csrf_token, CSRF_FIELD_NAME};
+use crate::authentication::middleware::UserId;
+use crate::config::SubscriptionSettings;
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::data_request_token::erased_email_hash;
+use crate::domain::new_subscriber::NewSubscriber;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_name::SubscriberName;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::routes::subscriber_data::post::is_erased_subscriber;
+use crate::routes::subscriptions::{
+    generate_subscription_token, insert_token, send_confirmation_email,
+};
+use crate::session_state::TypedSession;
+use crate::subscription_history::{record_subscription_event, SubscriptionEvent};
+use crate::utils::middleware::{e400, e500};
+
+/// Generous enough for tens of thousands of rows.
+const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+pub async fn import_subscribers_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+    // Multipart bodies are not searched for the CSRF token, it goes in the query string.
+    let csrf_token = csrf_token(&session).map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <p>Upload a CSV file with a header row and an <code>email</code> and a <code>name</code> column.
+    Addresses already on the list are skipped.</p>
+    <form action="/admin/subscribers/import?{CSRF_FIELD_NAME}={csrf_token}" method="post" enctype="multipart/form-data">
+        <input type="file" accept=".csv,text/csv" name="file">
+        <br>
+        <label>
+            <input type="radio" name="mode" value="send_confirmation" checked>
+            Send them a confirmation email
+        </label>
+        <label>
+            <input type="radio" name="mode" value="confirmed">
+            Import them as confirmed, they have opted in elsewhere
+        </label>
+        <br>
+        <button type="submit">Import</button>
+    </form>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ImportMode {
+    Confirmed,
+    SendConfirmation,
+}
+
+impl TryFrom<String> for ImportMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "confirmed" => Ok(ImportMode::Confirmed),
+            "send_confirmation" => Ok(ImportMode::SendConfirmation),
+            other => Err(format!("{} is not a supported import mode.", other)),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ImportRow {
+    email: String,
+    name: String,
+}
+
+/// A line of the file that could not be imported.
+#[derive(Debug, PartialEq)]
+struct LineError {
+    line: u64,
+    message: String,
+}
+
+#[derive(Debug)]
+struct ParsedImport {
+    subscribers: Vec<NewSubscriber>,
+    errors: Vec<LineError>,
+}
+
+/// Validates every row on its own, a bad line does not stop the others from
+/// being imported. An address appearing twice is only kept the first time.
+fn parse_import(data: &[u8]) -> Result<ParsedImport, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("The file is not valid CSV: {}", e))?
+        .clone();
+    if !headers.iter().any(|h| h == "email") || !headers.iter().any(|h| h == "name") {
+        return Err("The file needs a header row with an `email` and a `name` column.".into());
+    }
+
+    let mut subscribers = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen: HashMap<String, u64> = HashMap::new();
+    for result in reader.records() {
+        let (line, row) = match result {
+            Ok(record) => {
+                let line = record.position().map(|p| p.line()).unwrap_or_default();
+                (line, record.deserialize::<ImportRow>(Some(&headers)))
+            }
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                errors.push(LineError {
+                    line,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                errors.push(LineError {
+                    line,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let subscriber = SubscriberEmail::parse(row.email).and_then(|email| {
+            Ok(NewSubscriber {
+                email,
+                name: SubscriberName::parse(row.name)?,
+                locale: None,
+            })
+        });
+        match subscriber {
+            Ok(subscriber) => match seen.get(subscriber.email.as_ref()) {
+                Some(first_line) => errors.push(LineError {
+                    line,
+                    message: format!(
+                        "{} already appears on line {}.",
+                        subscriber.email, first_line
+                    ),
+                }),
+                None => {
+                    seen.insert(subscriber.email.as_ref().to_owned(), line);
+                    subscribers.push(subscriber);
+                }
+            },
+            Err(message) => errors.push(LineError { line, message }),
+        }
+    }
+    Ok(ParsedImport {
+        subscribers,
+        errors,
+    })
+}
+
+/// Reads the `file` and `mode` fields of the upload.
+async fn read_upload(mut payload: Multipart) -> Result<(Vec<u8>, ImportMode), actix_web::Error> {
+    let mut file = None;
+    let mut mode = None;
+    while let Some(mut field) = payload.try_next().await? {
+        let name = field.content_disposition().get_name().map(str::to_owned);
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_UPLOAD_BYTES {
+                return Err(e400(format!(
+                    "The file is too large, the limit is {} MiB.",
+                    MAX_UPLOAD_BYTES / 1024 / 1024
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        match name.as_deref() {
+            Some("file") => file = Some(data),
+            Some("mode") => {
+                let value = String::from_utf8(data).map_err(e400)?;
+                mode = Some(ImportMode::try_from(value).map_err(e400)?);
+            }
+            _ => {}
+        }
+    }
+    match (file, mode) {
+        (Some(file), Some(mode)) => Ok((file, mode)),
+        _ => Err(e400("The upload needs a `file` and a `mode`.")),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(payload, pool, email_client, templates, base_url, settings, secret, user_id),
+    fields(n_imported = tracing::field::Empty, n_skipped = tracing::field::Empty)
+)]
+pub async fn import_subscribers(
+    payload: Multipart,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let (file, mode) = read_upload(payload).await?;
+    let ParsedImport {
+        subscribers,
+        errors,
+    } = parse_import(&file).map_err(e400)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let mut n_imported = 0;
+    let mut n_skipped = 0;
+    let mut n_erased = 0;
+    let mut to_confirm = Vec::new();
+    for subscriber in subscribers {
+        let outcome = insert_imported_subscriber(
+            &mut transaction,
+            &subscriber,
+            &erased_email_hash(subscriber.email.as_ref(), &secret),
+            mode,
+            *user_id,
+            settings.token_ttl_hours,
+        )
+        .await
+        .map_err(e500)?;
+        match outcome {
+            ImportOutcome::AlreadySubscribed => n_skipped += 1,
+            ImportOutcome::Erased => n_erased += 1,
+            ImportOutcome::Confirmed => n_imported += 1,
+            ImportOutcome::PendingConfirmation(token) => {
+                n_imported += 1;
+                to_confirm.push((subscriber, token));
+            }
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers")
+        .map_err(e500)?;
+
+    tracing::Span::current()
+        .record("n_imported", &n_imported)
+        .record("n_skipped", &n_skipped);
+    if !to_confirm.is_empty() {
+        // A large file means a lot of emails, they go out once the response is sent.
+        tokio::spawn(
+            send_confirmation_emails(
+                email_client.into_inner(),
+                templates.into_inner(),
+                base_url.0.clone(),
+                to_confirm,
+            )
+            .in_current_span(),
+        );
+    }
+
+    let mut errors_html = String::new();
+    if !errors.is_empty() {
+        writeln!(
+            errors_html,
+            "<p>{} line(s) could not be imported:</p>\n    <ul>",
+            errors.len()
+        )
+        .unwrap();
+        for error in &errors {
+            writeln!(
+                errors_html,
+                "        <li>Line {}: {}</li>",
+                error.line,
+                encode_minimal(&error.message)
+            )
+            .unwrap();
+        }
+        errors_html.push_str("    </ul>");
+    }
+    let erased_html = if n_erased > 0 {
+        format!(
+            "<p>Skipped {n_erased} address(es) whose owner asked for their data to be erased.</p>"
+        )
+    } else {
+        String::new()
+    };
+    let confirmation_html = match mode {
+        ImportMode::Confirmed => "",
+        ImportMode::SendConfirmation => {
+            "<p>New subscribers are being sent a confirmation email.</p>"
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    <p>Imported {n_imported} subscriber(s), skipped {n_skipped} already on the list.</p>
+    {erased_html}
+    {confirmation_html}
+    {errors_html}
+    <p><a href="/admin/subscribers/import">Import another file</a></p>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+enum ImportOutcome {
+    AlreadySubscribed,
+    /// The owner of the address had us erase their data, it stays off the list.
+    Erased,
+    Confirmed,
+    /// Carries the token to send in the confirmation link.
+    PendingConfirmation(String),
+}
+
+#[tracing::instrument(
+    name = "Saving an imported subscriber",
+    skip(transaction, subscriber, email_hash, actor, ttl_hours)
+)]
+async fn insert_imported_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &NewSubscriber,
+    email_hash: &str,
+    mode: ImportMode,
+    actor: Uuid,
+    ttl_hours: i64,
+) -> Result<ImportOutcome, anyhow::Error> {
+    if is_erased_subscriber(transaction, email_hash).await? {
+        return Ok(ImportOutcome::Erased);
+    }
+
+    let subscription_id = Uuid::new_v4();
+    let status = match mode {
+        ImportMode::Confirmed => "confirmed",
+        ImportMode::SendConfirmation => "pending_confirmation",
+    };
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        subscription_id,
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        Utc::now(),
+        status
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert an imported subscriber")?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        return Ok(ImportOutcome::AlreadySubscribed);
+    }
+    record_subscription_event(
+        transaction,
+        subscription_id,
+        SubscriptionEvent::Imported,
+        Some(actor),
+    )
+    .await?;
+
+    match mode {
+        ImportMode::Confirmed => Ok(ImportOutcome::Confirmed),
+        ImportMode::SendConfirmation => {
+            let token = generate_subscription_token();
+            insert_token(transaction, subscription_id, &token, ttl_hours)
+                .await
+                .context("Failed to store a confirmation token")?;
+            record_subscription_event(
+                transaction,
+                subscription_id,
+                SubscriptionEvent::ConfirmationSent,
+                Some(actor),
+            )
+            .await?;
+            Ok(ImportOutcome::PendingConfirmation(token))
+        }
+    }
+}
+
+async fn send_confirmation_emails(
+    email_client: Arc<dyn EmailTransport>,
+    templates: Arc<EmailTemplates>,
+    base_url: String,
+    to_confirm: Vec<(NewSubscriber, String)>,
+) {
+    for (subscriber, token) in to_confirm {
+        let email = subscriber.email.as_ref().to_owned();
+        if let Err(e) = send_confirmation_email(
+            email_client.as_ref(),
+            &templates,
+            subscriber,
+            &base_url,
+            &token,
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %email,
+                "Failed to send the confirmation email to an imported subscriber"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_import;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn valid_rows_are_parsed() {
+        let data = "email,name\nursula@example.com,Ursula\n  le.guin@example.com , Le Guin \n";
+        let parsed = assert_ok!(parse_import(data.as_bytes()));
+        assert_eq!(parsed.subscribers.len(), 2);
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.subscribers[1].email.as_ref(), "le.guin@example.com");
+        assert_eq!(parsed.subscribers[1].name.as_ref(), "Le Guin");
+    }
+
+    #[test]
+    fn columns_can_come_in_any_order() {
+        let data = "name,email\nUrsula,ursula@example.com\n";
+        let parsed = assert_ok!(parse_import(data.as_bytes()));
+        assert_eq!(parsed.subscribers[0].email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_their_line() {
+        let data = "email,name\nursula@example.com,Ursula\nnot-an-email,Bob\nbob@example.com,\n";
+        let parsed = assert_ok!(parse_import(data.as_bytes()));
+        assert_eq!(parsed.subscribers.len(), 1);
+        let lines: Vec<u64> = parsed.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![3, 4]);
+    }
+
+    #[test]
+    fn duplicate_addresses_are_only_imported_once() {
+        let data = "email,name\nursula@example.com,Ursula\nursula@example.com,Ursula again\n";
+        let parsed = assert_ok!(parse_import(data.as_bytes()));
+        assert_eq!(parsed.subscribers.len(), 1);
+        assert_eq!(parsed.errors[0].line, 3);
+        assert!(parsed.errors[0].message.contains("line 2"));
+    }
+
+    #[test]
+    fn a_file_without_the_expected_header_is_rejected() {
+        assert_err!(parse_import("ursula@example.com,Ursula\n".as_bytes()));
+    }
+}
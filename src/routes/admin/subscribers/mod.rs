@@ -0,0 +1,4 @@
+pub mod export;
+pub mod get;
+pub mod import;
+pub mod post;
@@ -0,0 +1,212 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::config::SubscriptionSettings;
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::data_request_token::erased_email_hash;
+use crate::domain::new_subscriber::NewSubscriber;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_locale::SubscriberLocale;
+use crate::domain::subscriber_name::SubscriberName;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::routes::subscriber_data::post::is_erased_subscriber;
+use crate::routes::subscriptions::{
+    delete_subscriber, delete_tokens, generate_subscription_token, insert_token,
+    send_confirmation_email,
+};
+use crate::routes::unsubscribe::mark_subscriber_as_unsubscribed;
+use crate::subscription_history::{record_subscription_event, SubscriptionEvent};
+use crate::utils::middleware::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberFormData {
+    subscriber_id: Uuid,
+}
+
+/// Replaces whatever confirmation link the subscriber still holds with a new one.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, email_client, templates, base_url, settings, secret, user_id),
+    fields(subscriber_id = %form.subscriber_id)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<SubscriberFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let subscriber_id = form.subscriber_id;
+    let location = format!("/admin/subscribers/{}", subscriber_id);
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let subscriber = match get_pending_subscriber(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => subscriber,
+        None => {
+            FlashMessage::error("Only subscribers pending confirmation can be sent a new link.")
+                .send();
+            return Ok(see_other(&location));
+        }
+    };
+    // Someone else may have signed the address up, its owner will have to do it
+    // themselves.
+    let email_hash = erased_email_hash(subscriber.email.as_ref(), &secret);
+    if is_erased_subscriber(&mut transaction, &email_hash)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error(
+            "The owner of this address asked for their data to be erased, it cannot be sent a new link.",
+        )
+        .send();
+        return Ok(see_other(&location));
+    }
+    delete_tokens(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to remove previous confirmation tokens")
+        .map_err(e500)?;
+    let subscription_token = generate_subscription_token();
+    insert_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        settings.token_ttl_hours,
+    )
+    .await
+    .context("Failed to store a new confirmation token")
+    .map_err(e500)?;
+    record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionEvent::ConfirmationSent,
+        Some(*user_id),
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to resend a confirmation email")
+        .map_err(e500)?;
+
+    let email = subscriber.email.as_ref().to_owned();
+    send_confirmation_email(
+        email_client.as_ref(),
+        &templates,
+        subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send confirmation email")
+    .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "A new confirmation email has been sent to {}.",
+        email
+    ))
+    .send();
+    Ok(see_other(&location))
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber on their behalf",
+    skip(form, pool, user_id),
+    fields(subscriber_id = %form.subscriber_id)
+)]
+pub async fn unsubscribe_subscriber(
+    form: web::Form<SubscriberFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    mark_subscriber_as_unsubscribed(&pool, form.subscriber_id, Some(*user_id))
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The subscriber has been unsubscribed.").send();
+    Ok(see_other(&format!(
+        "/admin/subscribers/{}",
+        form.subscriber_id
+    )))
+}
+
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(form, pool),
+    fields(subscriber_id = %form.subscriber_id)
+)]
+pub async fn delete_subscriber_endpoint(
+    form: web::Form<SubscriberFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let deleted = delete_subscriber(&mut transaction, form.subscriber_id)
+        .await
+        .context("Failed to delete a subscriber")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber")
+        .map_err(e500)?;
+
+    if deleted {
+        FlashMessage::info("The subscriber has been deleted.").send();
+    } else {
+        FlashMessage::error("There is no such subscriber.").send();
+    }
+    Ok(see_other("/admin/subscribers"))
+}
+
+#[tracing::instrument(name = "Get pending subscriber", skip(transaction))]
+async fn get_pending_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<NewSubscriber>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email, name, locale
+        FROM subscriptions
+        WHERE id = $1 AND status = 'pending_confirmation'
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a pending subscriber.")?;
+
+    match row {
+        Some(row) => Ok(Some(NewSubscriber {
+            email: SubscriberEmail::parse(row.email).map_err(anyhow::Error::msg)?,
+            name: SubscriberName::parse(row.name).map_err(anyhow::Error::msg)?,
+            locale: row
+                .locale
+                .map(SubscriberLocale::parse)
+                .transpose()
+                .map_err(anyhow::Error::msg)?,
+        })),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::middleware::UserId;
+use crate::authentication::totp::{
+    encode_secret, encrypt_secret, generate_secret, otpauth_uri, qr_code_svg,
+};
+use crate::authentication::two_factor::{get_two_factor, store_pending_secret};
+use crate::domain::application::TotpEncryptionKey;
+use crate::routes::admin::dashboard::get_username;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+const ISSUER: &str = "zero2prod";
+
+pub async fn two_factor_settings(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    key: web::Data<TotpEncryptionKey>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+
+    let enabled = get_two_factor(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .map(|two_factor| two_factor.enabled)
+        .unwrap_or(false);
+
+    let body = if enabled {
+        format!(
+            r#"<p>Two-factor authentication is enabled.</p>
+    <form action="/admin/2fa/disable" method="post">
+        {csrf_field}
+        <label>Authentication or recovery code
+            <input type="text" autocomplete="one-time-code" name="code">
+        </label>
+        <button type="submit">Disable two-factor authentication</button>
+    </form>"#
+        )
+    } else {
+        // Every visit starts the enrolment over with a new secret, it only
+        // becomes active once a code generated from it is submitted.
+        let secret = generate_secret();
+        let encrypted = encrypt_secret(&secret, &key).map_err(e500)?;
+        store_pending_secret(*user_id, &encrypted, &pool)
+            .await
+            .map_err(e500)?;
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        let uri = otpauth_uri(ISSUER, &username, &secret);
+        let qr_code = qr_code_svg(&uri).map_err(e500)?;
+        format!(
+            r#"<p>Scan this QR code with your authenticator app:</p>
+    {qr_code}
+    <p>Or enter this key manually: <code id="totp-secret">{secret}</code></p>
+    <p><a href="{uri}">{uri}</a></p>
+    <form action="/admin/2fa/enable" method="post">
+        {csrf_field}
+        <label>Code from your app
+            <input type="text" inputmode="numeric" autocomplete="one-time-code" name="code">
+        </label>
+        <button type="submit">Enable two-factor authentication</button>
+    </form>"#,
+            secret = encode_secret(&secret),
+            uri = encode_minimal(&uri),
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {body}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
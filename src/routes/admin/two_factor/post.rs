@@ -0,0 +1,89 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::authentication::middleware::UserId;
+use crate::authentication::two_factor::{
+    disable_two_factor, enable_two_factor, verify_second_factor, verify_totp,
+};
+use crate::domain::application::TotpEncryptionKey;
+use crate::utils::middleware::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+/// Recovery codes are rendered straight into the response, they are only ever shown once.
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(form, pool, user_id, key)
+)]
+pub async fn enable_two_factor_endpoint(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    key: web::Data<TotpEncryptionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if !verify_totp(*user_id, &form.code, &pool, &key)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The code is not valid, please scan the new QR code and try again.")
+            .send();
+        return Ok(see_other("/admin/2fa"));
+    }
+    let recovery_codes = enable_two_factor(*user_id, &pool).await.map_err(e500)?;
+
+    let mut codes_html = String::new();
+    for code in recovery_codes {
+        writeln!(codes_html, "<li><code>{}</code></li>", code).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    <p>Two-factor authentication is now enabled.</p>
+    <p>Store these recovery codes somewhere safe, each one can be used once
+    to log in without your authenticator app. They will not be shown again.</p>
+    <ul id="recovery-codes">
+        {codes_html}
+    </ul>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(form, pool, user_id, key)
+)]
+pub async fn disable_two_factor_endpoint(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    key: web::Data<TotpEncryptionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if !verify_second_factor(*user_id, &form.code, &pool, &key)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The code is not valid.").send();
+        return Ok(see_other("/admin/2fa"));
+    }
+    disable_two_factor(*user_id, &pool).await.map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/2fa"))
+}
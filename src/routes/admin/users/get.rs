@@ -0,0 +1,185 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::middleware::UserId;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+struct UserRow {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: String,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+struct PendingInvite {
+    email: String,
+    role: String,
+    expires_at: DateTime<Utc>,
+}
+
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+
+    let mut users_html = String::new();
+    for user in get_users(&pool).await.map_err(e500)? {
+        // Owners cannot lock themselves out.
+        let action = if user.user_id == *user_id {
+            String::new()
+        } else {
+            let (path, label) = match user.disabled_at {
+                Some(_) => ("/admin/users/enable", "Enable"),
+                None => ("/admin/users/disable", "Disable"),
+            };
+            format!(
+                r#"<form action="{path}" method="post">
+                    {csrf_field}
+                    <input hidden type="text" name="user_id" value="{}">
+                    <button type="submit">{label}</button>
+                </form>"#,
+                user.user_id
+            )
+        };
+        writeln!(
+            users_html,
+            r#"<tr>
+            <td>{username}</td>
+            <td>{email}</td>
+            <td>{role}</td>
+            <td>{status}</td>
+            <td>{action}</td>
+        </tr>"#,
+            username = encode_minimal(&user.username),
+            email = encode_minimal(user.email.as_deref().unwrap_or("")),
+            role = user.role,
+            status = if user.disabled_at.is_some() {
+                "Disabled"
+            } else {
+                "Active"
+            },
+        )
+        .unwrap();
+    }
+
+    let mut invites_html = String::new();
+    for invite in get_pending_invites(&pool).await.map_err(e500)? {
+        writeln!(
+            invites_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            encode_minimal(&invite.email),
+            invite.role,
+            invite.expires_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Users</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Username</th>
+            <th>Email</th>
+            <th>Role</th>
+            <th>Status</th>
+            <th></th>
+        </tr>
+        {users_html}
+    </table>
+    <h2>Pending invites</h2>
+    <table>
+        <tr>
+            <th>Email</th>
+            <th>Role</th>
+            <th>Expires at</th>
+        </tr>
+        {invites_html}
+    </table>
+    <h2>Invite someone</h2>
+    <form action="/admin/users/invite" method="post">
+        {csrf_field}
+        <label>Email
+            <input type="email" placeholder="Enter their email" name="email">
+        </label>
+        <label>Role
+            <select name="role">
+                <option value="viewer">Viewer</option>
+                <option value="editor">Editor</option>
+                <option value="owner">Owner</option>
+            </select>
+        </label>
+        <button type="submit">Send invite</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Get users", skip(pool))]
+async fn get_users(pool: &PgPool) -> Result<Vec<UserRow>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        UserRow,
+        r#"
+        SELECT user_id, username, email, role, disabled_at
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve users.")?;
+    Ok(users)
+}
+
+#[tracing::instrument(name = "Get pending invites", skip(pool))]
+async fn get_pending_invites(pool: &PgPool) -> Result<Vec<PendingInvite>, anyhow::Error> {
+    let invites = sqlx::query_as!(
+        PendingInvite,
+        r#"
+        SELECT email, role, expires_at
+        FROM user_invites
+        WHERE
+            accepted_at IS NULL AND
+            expires_at > now()
+        ORDER BY created_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve pending invites.")?;
+    Ok(invites)
+}
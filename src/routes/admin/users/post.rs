@@ -0,0 +1,184 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::authentication::role::Role;
+use crate::authentication::session_registry::SessionRegistry;
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::invite_token::invite_link;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::transport::EmailTransport;
+use crate::utils::middleware::{e500, see_other};
+
+/// How long an account setup link stays valid.
+pub const INVITE_TTL_HOURS: i64 = 72;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    email: String,
+    role: String,
+}
+
+#[tracing::instrument(
+    name = "Invite a new user",
+    skip(form, pool, email_client, base_url, secret, user_id),
+    fields(invitee_email = %form.email, role = %form.role)
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let InviteFormData { email, role } = form.0;
+    let (email, role) = match (SubscriberEmail::parse(email), Role::try_from(role)) {
+        (Ok(email), Ok(role)) => (email, role),
+        (Err(e), _) | (_, Err(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+
+    if email_is_taken(&pool, &email).await.map_err(e500)? {
+        FlashMessage::error(format!("{} already has an account.", email.as_ref())).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let invite_id = insert_invite(&pool, &email, role, *user_id)
+        .await
+        .map_err(e500)?;
+
+    let link = invite_link(&base_url.0, invite_id, &secret);
+    email_client
+        .send_email(
+            &email,
+            "You have been invited to manage our newsletter",
+            &format!(
+                "You have been invited to join as {}.<br />\
+                Click <a href=\"{}\">here</a> to set up your account.",
+                role, link
+            ),
+            &format!(
+                "You have been invited to join as {}.\nVisit {} to set up your account.",
+                role, link
+            ),
+        )
+        .await
+        .context("Failed to send the invite email")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("An invite has been sent to {}.", email.as_ref())).send();
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AccountFormData {
+    user_id: Uuid,
+}
+
+/// Disabling an account also bumps its session generation, logging it out everywhere.
+#[tracing::instrument(
+    name = "Disable a user",
+    skip(form, pool, registry, user_id),
+    fields(target_user_id = %form.user_id)
+)]
+pub async fn disable_user(
+    form: web::Form<AccountFormData>,
+    pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if form.user_id == *user_id {
+        FlashMessage::error("You cannot disable your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET
+            disabled_at = now(),
+            session_generation = session_generation + 1
+        WHERE
+            user_id = $1 AND
+            disabled_at IS NULL
+        "#,
+        form.user_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to disable a user.")
+    .map_err(e500)?;
+    registry
+        .revoke_all_except(form.user_id, None)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("The account has been disabled.").send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(
+    name = "Enable a user",
+    skip(form, pool),
+    fields(target_user_id = %form.user_id)
+)]
+pub async fn enable_user(
+    form: web::Form<AccountFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    sqlx::query!(
+        r#"UPDATE users SET disabled_at = NULL WHERE user_id = $1"#,
+        form.user_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to enable a user.")
+    .map_err(e500)?;
+    FlashMessage::info("The account has been enabled.").send();
+    Ok(see_other("/admin/users"))
+}
+
+async fn email_is_taken(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by email.")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Store invite", skip(pool, email))]
+async fn insert_invite(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    role: Role,
+    invited_by: Uuid,
+) -> Result<Uuid, anyhow::Error> {
+    let invite_id = Uuid::new_v4();
+    let created_at = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invites (invite_id, email, role, invited_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        invite_id,
+        email.as_ref(),
+        role.as_str(),
+        invited_by,
+        created_at,
+        created_at + chrono::Duration::hours(INVITE_TTL_HOURS)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an invite.")?;
+    Ok(invite_id)
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::invite_token::verify_invite_token;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Parameters {
+    pub invite_id: Uuid,
+    pub token: String,
+}
+
+pub(crate) fn invalid_invite() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Set up your account</title>
+</head>
+<body>
+    <p>This invite link is not valid, it may have expired or been used already.</p>
+</body>
+</html>"#,
+        )
+}
+
+#[tracing::instrument(
+    name = "Show account setup form",
+    skip(parameters, secret, flash_messages)
+)]
+pub async fn accept_invite_form(
+    parameters: web::Query<Parameters>,
+    secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> HttpResponse {
+    if !verify_invite_token(&parameters.token, parameters.invite_id, &secret) {
+        return invalid_invite();
+    }
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Set up your account</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/invites/accept?invite_id={invite_id}&amp;token={token}" method="post">
+        <label>Username
+            <input type="text" placeholder="Pick a username" name="username">
+        </label>
+        <br>
+        <label>Password
+            <input type="password" placeholder="Pick a password" name="password">
+        </label>
+        <br>
+        <label>Confirm password
+            <input type="password" placeholder="Type the password again" name="password_check">
+        </label>
+        <br>
+        <button type="submit">Create my account</button>
+    </form>
+</body>
+</html>"#,
+            invite_id = parameters.invite_id,
+            token = encode_minimal(&parameters.token),
+        ))
+}
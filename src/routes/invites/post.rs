@@ -0,0 +1,153 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::auth::compute_password_hash;
+use crate::authentication::password_policy::validate_new_password;
+use crate::config::PasswordHashingSettings;
+use crate::domain::application::HmacSecret;
+use crate::domain::invite_token::verify_invite_token;
+use crate::routes::invites::get::{invalid_invite, Parameters};
+use crate::telemetry::spawn_blocking_with_tracing;
+use crate::utils::middleware::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+/// Creates the account and consumes the invite in the same transaction: an
+/// invite can only ever produce one account.
+#[tracing::instrument(
+    name = "Accept an invite",
+    skip(parameters, form, pool, hashing, secret),
+    fields(invite_id = %parameters.invite_id, username = %form.username)
+)]
+pub async fn accept_invite(
+    parameters: web::Query<Parameters>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !verify_invite_token(&parameters.token, parameters.invite_id, &secret) {
+        return Ok(invalid_invite());
+    }
+    let form_location = format!(
+        "/invites/accept?invite_id={}&token={}",
+        parameters.invite_id,
+        urlencoding::encode(&parameters.token)
+    );
+
+    let FormData {
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let username = username.trim().to_string();
+    if username.is_empty() {
+        FlashMessage::error("Please pick a username.").send();
+        return Ok(see_other(&form_location));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other(&form_location));
+    }
+    if let Err(e) = validate_new_password(&password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let invite = match get_pending_invite(&mut transaction, parameters.invite_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(invite) => invite,
+        None => return Ok(invalid_invite()),
+    };
+
+    let hashing = hashing.get_ref().clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await
+            .map_err(e500)?
+            .map_err(e500)?;
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        invite.role,
+        invite.email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to create the invited user.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted == 0 {
+        FlashMessage::error("This username is already taken, please pick another one.").send();
+        return Ok(see_other(&form_location));
+    }
+
+    sqlx::query!(
+        r#"UPDATE user_invites SET accepted_at = now() WHERE invite_id = $1"#,
+        parameters.invite_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the invite as accepted.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invite.")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your account is ready, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+struct PendingInvite {
+    email: String,
+    role: String,
+}
+
+#[tracing::instrument(name = "Get pending invite", skip(transaction))]
+async fn get_pending_invite(
+    transaction: &mut Transaction<'_, Postgres>,
+    invite_id: Uuid,
+) -> Result<Option<PendingInvite>, anyhow::Error> {
+    let invite = sqlx::query_as!(
+        PendingInvite,
+        r#"
+        SELECT email, role
+        FROM user_invites
+        WHERE
+            invite_id = $1 AND
+            accepted_at IS NULL AND
+            expires_at > now()
+        FOR UPDATE
+        "#,
+        invite_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve an invite.")?;
+    Ok(invite)
+}
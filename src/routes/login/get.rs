@@ -1,16 +1,24 @@
-use std::fmt::Formatter;
 use std::fmt::Write;
 
-use actix_web::cookie::time::Duration;
 use actix_web::cookie::Cookie;
 use actix_web::http::header::ContentType;
-use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
-use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</></p>", m.content()).unwrap();
+use crate::authentication::csrf::csrf_field;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::e500;
+
+pub async fn login_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Errors from a failed login as well as notices from wherever sent the
+    // user here, e.g. logging out or resetting their password.
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
     }
     let mut response = HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -25,6 +33,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
 <body>
     {}
     <form action="/login" method="post">
+        {}
         <label>Username
             <input
                 type="text"
@@ -41,13 +50,15 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
         </label>
         <button type="submit">Login</button>
     </form>
+    <p><a href="/password-reset">Forgot your password?</a></p>
 </body>
 </html>"#,
-            error_html
+            msg_html,
+            csrf_field(&session).map_err(e500)?
         ));
 
     response
         .add_removal_cookie(&Cookie::new("_flash", ""))
         .unwrap();
-    response
+    Ok(response)
 }
@@ -2,3 +2,4 @@
 
 pub mod get;
 pub mod post;
+pub mod two_factor;
@@ -1,22 +1,21 @@
 use std::fmt::Formatter;
 
-use actix_session::Session;
-use actix_web::body::BoxBody;
-use actix_web::cookie::Cookie;
 use actix_web::error::InternalError;
-use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use actix_web_flash_messages::FlashMessage;
-use hmac::{Hmac, Mac};
 use reqwest::header::LOCATION;
-use secrecy::ExposeSecret;
 use secrecy::Secret;
 use sqlx::PgPool;
 use tracing;
 
-use crate::authentication::auth::{validate_credentials, AuthError, Credentials};
-use crate::domain::application::HmacSecret;
+use crate::authentication::auth::{get_session_generation, AuthError, Credentials};
+use crate::authentication::session_registry::{ClientInfo, SessionRegistry};
+use crate::authentication::throttle::{
+    validate_throttled_credentials, LoginThrottle, ThrottledAuthError,
+};
+use crate::authentication::two_factor::is_two_factor_enabled;
+use crate::config::PasswordHashingSettings;
 use crate::session_state::TypedSession;
 use crate::utils::error_helpers::error_chain_fmt;
 
@@ -25,6 +24,9 @@ pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
 
+    #[error("Too many failed login attempts, try again in {} minute(s)", .0)]
+    LockedOut(u64),
+
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -56,13 +58,17 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 }
 
 #[tracing::instrument(
-skip(form, pool, session),
+skip(form, pool, hashing, session, throttle, registry, request),
 fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
 )]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
     session: TypedSession,
+    throttle: web::Data<LoginThrottle>,
+    registry: web::Data<SessionRegistry>,
+    request: HttpRequest,
     // secret: web::Data<HmacSecret>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
@@ -71,14 +77,45 @@ pub async fn login(
     };
 
     tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    match validate_credentials(credentials, &pool).await {
+    let client_ip = throttle.client_ip(&request);
+    match validate_throttled_credentials(&throttle, credentials, client_ip, &hashing, &pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
 
+            let two_factor_enabled = is_two_factor_enabled(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            if two_factor_enabled {
+                session.renew();
+                session
+                    .insert_pending_second_factor(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/2fa"))
+                    .finish());
+            }
+
+            let session_generation = get_session_generation(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+
             session.renew();
             session
                 .insert_user_id(user_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session
+                .insert_session_generation(session_generation)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            let session_id = registry
+                .register(
+                    user_id,
+                    ClientInfo::new(request.peer_addr(), request.headers()),
+                )
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            session
+                .insert_session_id(session_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
 
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
@@ -86,8 +123,15 @@ pub async fn login(
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
-                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+                ThrottledAuthError::LockedOut { retry_after } => {
+                    LoginError::LockedOut(retry_after.as_secs().div_ceil(60))
+                }
+                ThrottledAuthError::AuthError(AuthError::InvalidCredentials(_)) => {
+                    LoginError::AuthError(e.into())
+                }
+                ThrottledAuthError::AuthError(AuthError::UnexpectedError(_)) => {
+                    LoginError::UnexpectedError(e.into())
+                }
             };
 
             // let query_string = format!("error={}", urlencoding::Encoded::new(e.to_string()));
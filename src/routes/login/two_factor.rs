@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+
+use crate::authentication::auth::get_session_generation;
+use crate::authentication::csrf::csrf_field;
+use crate::authentication::session_registry::{ClientInfo, SessionRegistry};
+use crate::authentication::throttle::LoginThrottle;
+use crate::authentication::two_factor::verify_second_factor;
+use crate::domain::application::TotpEncryptionKey;
+use crate::session_state::TypedSession;
+use crate::utils::middleware::{e500, see_other};
+
+pub async fn two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_second_factor().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    let csrf_field = csrf_field(&session).map_err(e500)?;
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/login/2fa" method="post">
+        {csrf_field}
+        <label>Authentication code
+            <input
+                type="text"
+                inputmode="numeric"
+                autocomplete="one-time-code"
+                placeholder="Enter the code from your app or a recovery code"
+                name="code"
+            >
+        </label>
+        <button type="submit">Verify</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+#[tracing::instrument(
+    name = "Verify second factor",
+    skip(form, pool, session, key, registry, throttle, request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn verify_two_factor(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    key: web::Data<TotpEncryptionKey>,
+    registry: web::Data<SessionRegistry>,
+    throttle: web::Data<LoginThrottle>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_pending_second_factor().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error("Your login attempt has expired, please try again.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    if throttle
+        .is_second_factor_locked_out(user_id)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(too_many_invalid_codes(&session));
+    }
+    if !verify_second_factor(user_id, &form.code, &pool, &key)
+        .await
+        .map_err(e500)?
+    {
+        let client_ip = throttle.client_ip(&request);
+        if throttle
+            .record_second_factor_failure(user_id, client_ip, &pool)
+            .await
+            .map_err(e500)?
+        {
+            return Ok(too_many_invalid_codes(&session));
+        }
+        FlashMessage::error("The authentication code is not valid.").send();
+        return Ok(see_other("/login/2fa"));
+    }
+    throttle
+        .record_second_factor_success(user_id)
+        .await
+        .map_err(e500)?;
+
+    let session_generation = get_session_generation(user_id, &pool).await.map_err(e500)?;
+    session.remove_pending_second_factor();
+    session.renew();
+    session.insert_user_id(user_id).map_err(e500)?;
+    session
+        .insert_session_generation(session_generation)
+        .map_err(e500)?;
+    let session_id = registry
+        .register(
+            user_id,
+            ClientInfo::new(request.peer_addr(), request.headers()),
+        )
+        .await
+        .map_err(e500)?;
+    session.insert_session_id(session_id).map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// The password has to be entered again, and even then no code is checked until
+/// the lockout expires.
+fn too_many_invalid_codes(session: &TypedSession) -> HttpResponse {
+    session.remove_pending_second_factor();
+    FlashMessage::error("Too many invalid authentication codes, please try again later.").send();
+    see_other("/login")
+}
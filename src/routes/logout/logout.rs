@@ -1,15 +1,23 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 
+use crate::authentication::session_registry::SessionRegistry;
 use crate::session_state::TypedSession;
 use crate::utils::middleware::{e500, see_other};
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        Ok(see_other("/login"))
-    } else {
-        session.log_out();
-        FlashMessage::info("You have successfully logged out.").send();
-        Ok(see_other("/login"))
+pub async fn log_out(
+    session: TypedSession,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match session.get_user_id().map_err(e500)? {
+        None => Ok(see_other("/login")),
+        Some(user_id) => {
+            if let Some(session_id) = session.get_session_id().map_err(e500)? {
+                registry.revoke(user_id, session_id).await.map_err(e500)?;
+            }
+            session.log_out();
+            FlashMessage::info("You have successfully logged out.").send();
+            Ok(see_other("/login"))
+        }
     }
 }
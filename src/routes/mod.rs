@@ -1,8 +1,12 @@
 pub mod admin;
 pub mod health;
 pub mod home;
+pub mod invites;
 pub mod login;
 pub mod logout;
 pub mod newsletter;
+pub mod password_reset;
+pub mod subscriber_data;
 pub mod subscription_confirm;
 pub mod subscriptions;
+pub mod unsubscribe;
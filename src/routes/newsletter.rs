@@ -1,59 +1,45 @@
-use std::fmt::{Display, Formatter};
+use std::fmt::Formatter;
 
 use actix_web::body::BoxBody;
-use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::header::{ContentType, HeaderMap, HeaderValue};
 use actix_web::http::{header, StatusCode};
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
-use anyhow::{anyhow, Context};
-use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
-use secrecy::ExposeSecret;
+use anyhow::Context;
+use chrono::Utc;
 use secrecy::Secret;
-use sha3::Digest;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
+use crate::authentication::api_token::{validate_api_token, ApiTokenScope};
 use crate::authentication::auth::*;
-use crate::domain::subscriber_email::SubscriberEmail;
-use crate::mail::send_email::EmailClient;
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::authentication::role::Role;
+use crate::authentication::throttle::{
+    validate_throttled_credentials, LoginThrottle, ThrottledAuthError,
+};
+use crate::authentication::two_factor::is_two_factor_enabled;
+use crate::config::PasswordHashingSettings;
+use crate::domain::send_at::SendAt;
+use crate::idempotency::key::IdempotencyKey;
+use crate::idempotency::persistence::{save_response, try_processing, NextAction};
+use crate::mail::markdown::{MarkdownRenderer, RenderedContent};
+use crate::mail::placeholders::validate_placeholders;
 use crate::utils::error_helpers::error_chain_fmt;
 
+/// Either both versions of the issue written by hand, or Markdown we render
+/// into both.
 #[derive(serde::Deserialize)]
-pub struct Content {
-    html: String,
-    text: String,
+#[serde(untagged, deny_unknown_fields)]
+pub enum Content {
+    Explicit { html: String, text: String },
+    Markdown { markdown: String },
 }
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
-}
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let rows = sqlx::query!(
-        r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-
-    Ok(rows)
+    /// RFC 3339, e.g. `2022-09-19T09:00:00+02:00`. Issues without one go out now.
+    send_at: Option<String>,
 }
 
 #[derive(thiserror::Error)]
@@ -63,6 +49,21 @@ pub enum PublishError {
 
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("A request with the same idempotency key is already being processed.")]
+    Conflict,
+
+    #[error("The user is not allowed to publish newsletters.")]
+    Forbidden,
+
+    #[error("Accounts with two-factor authentication enabled must use an API token.")]
+    ApiTokenRequired,
+
+    #[error("Too many failed authentication attempts.")]
+    TooManyAttempts { retry_after_secs: u64 },
 }
 
 impl std::fmt::Debug for PublishError {
@@ -75,7 +76,11 @@ impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::AuthError(_) | PublishError::ApiTokenRequired => StatusCode::UNAUTHORIZED,
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::Conflict => StatusCode::CONFLICT,
+            PublishError::Forbidden => StatusCode::FORBIDDEN,
+            PublishError::TooManyAttempts { .. } => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 
@@ -92,64 +97,266 @@ impl ResponseError for PublishError {
                     .insert(header::WWW_AUTHENTICATE, header_value);
                 response
             }
+            PublishError::ApiTokenRequired => HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .body(self.to_string()),
+            PublishError::TooManyAttempts { retry_after_secs } => {
+                let mut response = HttpResponse::new(StatusCode::TOO_MANY_REQUESTS);
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+                response
+            }
+            // Tell the API client what to fix.
+            PublishError::ValidationError(message) => HttpResponse::BadRequest()
+                .content_type(ContentType::plaintext())
+                .body(message.clone()),
+            PublishError::Conflict | PublishError::Forbidden => {
+                HttpResponse::new(self.status_code())
+            }
         }
     }
 }
 
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, hashing, throttle, markdown_renderer, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    hashing: web::Data<PasswordHashingSettings>,
+    throttle: web::Data<LoginThrottle>,
+    markdown_renderer: web::Data<MarkdownRenderer>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool)
-        .await
+    let (user_id, with_password) =
+        match authorization(request.headers()).map_err(PublishError::AuthError)? {
+            Authorization::Basic(credentials) => {
+                tracing::Span::current()
+                    .record("username", &tracing::field::display(&credentials.username));
+                let client_ip = throttle.client_ip(&request);
+                validate_throttled_credentials(&throttle, credentials, client_ip, &hashing, &pool)
+                    .await
+                    .map(|user_id| (user_id, true))
+            }
+            // API tokens are long random strings, guessing them is hopeless anyway.
+            Authorization::Bearer(token) => {
+                validate_api_token(token, ApiTokenScope::PublishNewsletters, &pool)
+                    .await
+                    .map(|user_id| (user_id, false))
+                    .map_err(ThrottledAuthError::from)
+            }
+        }
         .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+            ThrottledAuthError::LockedOut { retry_after } => PublishError::TooManyAttempts {
+                retry_after_secs: retry_after.as_secs(),
+            },
+            ThrottledAuthError::AuthError(AuthError::InvalidCredentials(_)) => {
+                PublishError::AuthError(e.into())
+            }
+            ThrottledAuthError::AuthError(AuthError::UnexpectedError(_)) => {
+                PublishError::UnexpectedError(e.into())
+            }
         })?;
     tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
 
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(sub) => {
-                email_client
-                    .send_email(
-                        &sub.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    // provides a lazy method approach, we only pay the cost of heap allocation for format! when there is actually an error
-                    // unlike .context() where we pay it regardless.
-                    .with_context(|| format!("Failed to send newsletter issue to {}", sub.email))?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "Skipping a confirmed subscriber. Their stored contract details is invalid."
-                );
-            }
+    // A password is a single factor. Accounts with two-factor authentication
+    // publish with an API token, which only a fully logged-in session can create.
+    if with_password && is_two_factor_enabled(user_id, &pool).await? {
+        return Err(PublishError::ApiTokenRequired);
+    }
+
+    // Viewers can log in with their password but not publish with it.
+    let account = get_account(user_id, &pool).await?;
+    if account.role < Role::Editor {
+        return Err(PublishError::Forbidden);
+    }
+
+    let idempotency_key = get_idempotency_key(request.headers())
+        .map_err(|e| PublishError::ValidationError(e.to_string()))?;
+
+    let BodyData {
+        title,
+        content,
+        send_at,
+    } = body.0;
+    let send_at = send_at
+        .as_deref()
+        .map(SendAt::parse)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
+    let content = match content {
+        Content::Explicit { html, text } => RenderedContent { html, text },
+        Content::Markdown { markdown } => markdown_renderer.render(&title, &markdown)?,
+    };
+    for part in [&title, &content.text, &content.html] {
+        validate_placeholders(part).map_err(PublishError::ValidationError)?;
+    }
+
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&pool, idempotency_key, user_id).await? {
+            NextAction::StartProcessing(transaction) => transaction,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+            NextAction::Conflict => return Err(PublishError::Conflict),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a postgres connection from the pool")?,
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &content.text,
+        &content.html,
+        send_at,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+
+    let response = match send_at {
+        // The delivery worker enqueues it once it is due.
+        Some(send_at) => HttpResponse::Accepted().json(serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "send_at": send_at.as_ref().to_rfc3339(),
+        })),
+        None => {
+            enqueue_delivery_tasks(&mut transaction, issue_id)
+                .await
+                .context("Failed to enqueue delivery tasks")?;
+            HttpResponse::Ok().finish()
         }
+    };
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(save_response(transaction, &idempotency_key, user_id, response).await?)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue")?;
+            Ok(response)
+        }
+    }
+}
+
+/// Idempotency keys are optional, clients that want safe retries send one
+/// through the `Idempotency-Key` header.
+fn get_idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, anyhow::Error> {
+    match headers.get("Idempotency-Key") {
+        Some(header_value) => {
+            let key = header_value
+                .to_str()
+                .context("The 'Idempotency-Key' header was not a valid UTF8 string.")?
+                .to_string();
+            Ok(Some(key.try_into()?))
+        }
+        None => Ok(None),
     }
-    Ok(HttpResponse::Ok().finish())
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+/// Issues with a `send_at` are stored as `scheduled`, the others are published
+/// right away and the caller enqueues their deliveries.
+#[tracing::instrument(name = "Store newsletter issue", skip_all)]
+pub(crate) async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    send_at: Option<SendAt>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let (status, send_at, published_at) = match send_at {
+        Some(send_at) => ("scheduled", Some(*send_at.as_ref()), None),
+        None => ("published", None, Some(Utc::now())),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            send_at,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        status,
+        send_at,
+        published_at
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Enqueue newsletter delivery tasks", skip_all)]
+pub(crate) async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+enum Authorization {
+    Basic(Credentials),
+    Bearer(Secret<String>),
+}
+
+/// Parses the `Authorization` header, we accept either `Basic` credentials or a
+/// `Bearer` API token. Schemes are case-insensitive as per RFC 7235.
+fn authorization(headers: &HeaderMap) -> Result<Authorization, anyhow::Error> {
     let header_value = headers
-        .get("Authentication")
+        .get(header::AUTHORIZATION)
         .context("The 'Authorization' header was missing")?
         .to_str()
         .context("The 'Authorization' header was not a valid UTF8 string.")?;
 
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
+    let (scheme, parameters) = header_value
+        .trim()
+        .split_once(' ')
+        .context("The 'Authorization' header is malformed.")?;
+    let parameters = parameters.trim();
+
+    if scheme.eq_ignore_ascii_case("Basic") {
+        basic_authentication(parameters).map(Authorization::Basic)
+    } else if scheme.eq_ignore_ascii_case("Bearer") {
+        if parameters.is_empty() {
+            anyhow::bail!("The 'Bearer' token was empty.");
+        }
+        Ok(Authorization::Bearer(Secret::new(parameters.to_string())))
+    } else {
+        anyhow::bail!("The authorization scheme was neither 'Basic' nor 'Bearer'.")
+    }
+}
 
+fn basic_authentication(base64encoded_segment: &str) -> Result<Credentials, anyhow::Error> {
     let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
         .context("Failed to base64-decode 'Basic' credentials.")?;
 
@@ -173,3 +380,44 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
         password: Secret::new(password),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use secrecy::ExposeSecret;
+
+    use super::{authorization, Authorization};
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn basic_credentials_are_read_from_the_authorization_header() {
+        let encoded = base64::encode("ursula:le-guin");
+        match authorization(&headers(&format!("Basic {}", encoded))).unwrap() {
+            Authorization::Basic(credentials) => {
+                assert_eq!(credentials.username, "ursula");
+                assert_eq!(credentials.password.expose_secret(), "le-guin");
+            }
+            Authorization::Bearer(_) => panic!("Expected basic credentials"),
+        }
+    }
+
+    #[test]
+    fn bearer_tokens_are_accepted_with_any_scheme_casing() {
+        match authorization(&headers("bearer z2p_token")).unwrap() {
+            Authorization::Bearer(token) => assert_eq!(token.expose_secret(), "z2p_token"),
+            Authorization::Basic(_) => panic!("Expected a bearer token"),
+        }
+    }
+
+    #[test]
+    fn unknown_schemes_and_missing_headers_are_rejected() {
+        assert!(authorization(&headers("Digest abc")).is_err());
+        assert!(authorization(&headers("Bearer ")).is_err());
+        assert!(authorization(&HeaderMap::new()).is_err());
+    }
+}
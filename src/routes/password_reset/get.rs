@@ -0,0 +1,110 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::password_reset_token::verify_password_reset_token;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Parameters {
+    pub reset_id: Uuid,
+    pub token: String,
+}
+
+pub(crate) fn invalid_reset_link() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset your password</title>
+</head>
+<body>
+    <p>This password reset link is not valid, it may have expired or been used already.</p>
+    <p><a href="/password-reset">Request a new link</a></p>
+</body>
+</html>"#,
+        )
+}
+
+pub async fn password_reset_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset your password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password-reset" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <button type="submit">Email me a reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(
+    name = "Show password reset form",
+    skip(parameters, secret, flash_messages),
+    fields(reset_id = %parameters.reset_id)
+)]
+pub async fn new_password_form(
+    parameters: web::Query<Parameters>,
+    secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> HttpResponse {
+    if !verify_password_reset_token(&parameters.token, parameters.reset_id, &secret) {
+        return invalid_reset_link();
+    }
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset your password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password-reset/confirm?reset_id={reset_id}&amp;token={token}" method="post">
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+            reset_id = parameters.reset_id,
+            token = encode_minimal(&parameters.token),
+        ))
+}
@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::authentication::auth::change_password;
+use crate::authentication::password_policy::validate_new_password;
+use crate::authentication::session_registry::SessionRegistry;
+use crate::config::PasswordHashingSettings;
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::password_reset_token::{password_reset_link, verify_password_reset_token};
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::routes::password_reset::get::{invalid_reset_link, Parameters};
+use crate::utils::middleware::{e500, see_other};
+
+/// How long a password reset link stays valid.
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+#[derive(serde::Deserialize)]
+pub struct RequestFormData {
+    username: String,
+}
+
+/// Answers the same way whether or not the username exists. Unknown usernames
+/// still get a reset id and a signed link, mirroring the fake password hash in
+/// `validate_credentials`, and the email is sent off the request so its latency
+/// does not give the answer away either.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, email_client, templates, base_url, secret),
+    fields(username = %form.username)
+)]
+pub async fn request_password_reset(
+    form: web::Form<RequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let account = get_resettable_account(&pool, form.username.trim())
+        .await
+        .map_err(e500)?;
+
+    let reset_id = Uuid::new_v4();
+    let link = password_reset_link(&base_url.0, reset_id, &secret);
+    if let Some((user_id, email)) = account {
+        insert_password_reset(&pool, reset_id, user_id)
+            .await
+            .map_err(e500)?;
+        tokio::spawn(
+            send_password_reset_email(
+                email_client.into_inner(),
+                templates.into_inner(),
+                email,
+                link,
+            )
+            .in_current_span(),
+        );
+    }
+
+    FlashMessage::info("If that account exists, we have emailed it a link to reset its password.")
+        .send();
+    Ok(see_other("/password-reset"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewPasswordFormData {
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Consumes every pending reset of the user and changes the password in one
+/// go. `change_password` bumps the session generation, so whoever was logged
+/// in with the old password is logged out.
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(parameters, form, pool, hashing, secret, registry),
+    fields(reset_id = %parameters.reset_id)
+)]
+pub async fn reset_password(
+    parameters: web::Query<Parameters>,
+    form: web::Form<NewPasswordFormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    secret: web::Data<HmacSecret>,
+    registry: web::Data<SessionRegistry>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !verify_password_reset_token(&parameters.token, parameters.reset_id, &secret) {
+        return Ok(invalid_reset_link());
+    }
+    let form_location = format!(
+        "/password-reset/confirm?reset_id={}&token={}",
+        parameters.reset_id,
+        urlencoding::encode(&parameters.token)
+    );
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&form_location));
+    }
+    if let Err(e) = validate_new_password(&form.new_password) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let user_id = match get_pending_reset(&mut transaction, parameters.reset_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => return Ok(invalid_reset_link()),
+    };
+    consume_password_resets(&mut transaction, user_id)
+        .await
+        .map_err(e500)?;
+    change_password(user_id, form.0.new_password, &hashing, &mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")
+        .map_err(e500)?;
+    registry
+        .revoke_all_except(user_id, None)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+/// Disabled accounts and accounts without an email address cannot be reset.
+#[tracing::instrument(name = "Get resettable account", skip(pool))]
+async fn get_resettable_account(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<(Uuid, SubscriberEmail)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, email as "email!"
+        FROM users
+        WHERE
+            username = $1 AND
+            disabled_at IS NULL AND
+            email IS NOT NULL
+        "#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the account to reset.")?;
+
+    match row {
+        Some(row) => {
+            let email = SubscriberEmail::parse(row.email).map_err(anyhow::Error::msg)?;
+            Ok(Some((row.user_id, email)))
+        }
+        None => Ok(None),
+    }
+}
+
+#[tracing::instrument(name = "Store password reset", skip(pool))]
+async fn insert_password_reset(
+    pool: &PgPool,
+    reset_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_resets (reset_id, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        reset_id,
+        user_id,
+        now,
+        now + Duration::minutes(PASSWORD_RESET_TTL_MINUTES)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the password reset.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send password reset email", skip_all)]
+async fn send_password_reset_email(
+    email_client: Arc<dyn EmailTransport>,
+    templates: Arc<EmailTemplates>,
+    email: SubscriberEmail,
+    link: String,
+) {
+    let mut context = tera::Context::new();
+    context.insert("reset_link", &link);
+    context.insert("ttl_minutes", &PASSWORD_RESET_TTL_MINUTES);
+    // Admins have no locale, they get the default one.
+    let result = match templates.render("password_reset", None, &context) {
+        Ok(message) => {
+            email_client
+                .send_email(&email, &message.subject, &message.html, &message.text)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a password reset email"
+        );
+    }
+}
+
+#[tracing::instrument(name = "Get pending password reset", skip(transaction))]
+async fn get_pending_reset(
+    transaction: &mut Transaction<'_, Postgres>,
+    reset_id: Uuid,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT password_resets.user_id
+        FROM password_resets
+        JOIN users ON users.user_id = password_resets.user_id
+        WHERE
+            password_resets.reset_id = $1 AND
+            password_resets.used_at IS NULL AND
+            password_resets.expires_at > now() AND
+            users.disabled_at IS NULL
+        FOR UPDATE OF password_resets
+        "#,
+        reset_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a password reset.")?;
+    Ok(row.map(|row| row.user_id))
+}
+
+#[tracing::instrument(name = "Consume password resets", skip(transaction))]
+async fn consume_password_resets(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE password_resets
+        SET used_at = now()
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to consume the password resets.")?;
+    Ok(())
+}
@@ -0,0 +1,380 @@
+use std::fmt::Write;
+
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::application::HmacSecret;
+use crate::domain::data_request_token::verify_data_request_token;
+use crate::utils::middleware::e500;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Parameters {
+    pub request_id: Uuid,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataRequestKind {
+    Export,
+    Erase,
+}
+
+impl DataRequestKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataRequestKind::Export => "export",
+            DataRequestKind::Erase => "erase",
+        }
+    }
+}
+
+impl TryFrom<String> for DataRequestKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "export" => Ok(DataRequestKind::Export),
+            "erase" => Ok(DataRequestKind::Erase),
+            other => Err(format!("{} is not a supported request.", other)),
+        }
+    }
+}
+
+pub(crate) fn page(body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#,
+    )
+}
+
+pub(crate) fn invalid_data_request_link() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .content_type(ContentType::html())
+        .body(page(
+            r#"<p>This link is not valid, it may have expired or been used already.</p>
+    <p><a href="/subscriptions/my-data">Make a new request</a></p>"#,
+        ))
+}
+
+pub async fn data_request_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page(&format!(
+            r#"{msg_html}
+    <p>You can get a copy of everything we hold about your subscription, or have all of it erased.
+    We will email you a link to confirm it is you.</p>
+    <form action="/subscriptions/my-data" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <br>
+        <label>
+            <input type="radio" name="kind" value="export" checked>
+            Send me a copy of my data
+        </label>
+        <label>
+            <input type="radio" name="kind" value="erase">
+            Erase my data
+        </label>
+        <br>
+        <button type="submit">Send me a link</button>
+    </form>"#,
+        )))
+}
+
+/// Exports are served straight off the link and can be downloaded again until it
+/// expires. Erasure only asks for a confirmation, for the same reason as unsubscribing:
+/// link scanners must not be able to erase anyone.
+#[tracing::instrument(
+    name = "Follow a subscriber data request link",
+    skip(parameters, pool, secret),
+    fields(request_id = %parameters.request_id)
+)]
+pub async fn confirm_data_request(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !verify_data_request_token(&parameters.token, parameters.request_id, &secret) {
+        return Ok(invalid_data_request_link());
+    }
+    let (subscription_id, kind) = match get_pending_data_request(&pool, parameters.request_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(request) => request,
+        None => return Ok(invalid_data_request_link()),
+    };
+
+    match kind {
+        DataRequestKind::Export => {
+            let data = get_subscriber_data(&pool, subscription_id)
+                .await
+                .map_err(e500)?;
+            Ok(HttpResponse::Ok()
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename("my-data.json".into())],
+                })
+                .json(data))
+        }
+        DataRequestKind::Erase => Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(page(&format!(
+                r#"<p>Do you want us to erase all the data we hold about your subscription?
+    You will stop receiving our newsletter, this cannot be undone.</p>
+    <form action="/subscriptions/my-data/confirm?request_id={request_id}&amp;token={token}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>"#,
+                request_id = parameters.request_id,
+                token = encode_minimal(&parameters.token),
+            )))),
+    }
+}
+
+#[tracing::instrument(name = "Get pending subscriber data request", skip(pool))]
+async fn get_pending_data_request(
+    pool: &PgPool,
+    request_id: Uuid,
+) -> Result<Option<(Uuid, DataRequestKind)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT subscription_id, kind
+        FROM subscriber_data_requests
+        WHERE request_id = $1 AND expires_at > now()
+        "#,
+        request_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a subscriber data request.")?;
+
+    match row {
+        Some(row) => {
+            let kind = DataRequestKind::try_from(row.kind).map_err(anyhow::Error::msg)?;
+            Ok(Some((row.subscription_id, kind)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Timestamps are RFC 3339 strings, the export is meant to be read by people
+/// as much as by programs.
+#[derive(serde::Serialize)]
+pub struct SubscriberData {
+    subscription: SubscriptionData,
+    confirmation_tokens: Vec<TokenData>,
+    history: Vec<EventData>,
+    pending_deliveries: Vec<PendingDeliveryData>,
+    failed_deliveries: Vec<FailedDeliveryData>,
+    data_requests: Vec<DataRequestData>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionData {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: String,
+    /// `None` when they get emails in the default locale.
+    locale: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct TokenData {
+    token: String,
+    created_at: String,
+    expires_at: String,
+}
+
+#[derive(serde::Serialize)]
+struct EventData {
+    event: String,
+    occurred_at: String,
+}
+
+#[derive(serde::Serialize)]
+struct PendingDeliveryData {
+    newsletter_issue_id: Uuid,
+    title: String,
+    n_retries: i32,
+    next_attempt_at: String,
+}
+
+#[derive(serde::Serialize)]
+struct FailedDeliveryData {
+    newsletter_issue_id: Uuid,
+    title: String,
+    n_retries: i32,
+    last_error: String,
+    failed_at: String,
+}
+
+#[derive(serde::Serialize)]
+struct DataRequestData {
+    kind: String,
+    created_at: String,
+    expires_at: String,
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339()
+}
+
+#[tracing::instrument(name = "Collect subscriber data", skip(pool))]
+async fn get_subscriber_data(
+    pool: &PgPool,
+    subscription_id: Uuid,
+) -> Result<SubscriberData, anyhow::Error> {
+    let subscription = sqlx::query!(
+        r#"
+        SELECT id, email, name, status, subscribed_at, locale
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscription_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the subscription.")?;
+
+    let confirmation_tokens = sqlx::query!(
+        r#"
+        SELECT subscription_token, created_at, expires_at
+        FROM subscriptions_tokens
+        WHERE subscription_id = $1
+        ORDER BY created_at
+        "#,
+        subscription_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the confirmation tokens.")?
+    .into_iter()
+    .map(|row| TokenData {
+        token: row.subscription_token,
+        created_at: timestamp(row.created_at),
+        expires_at: timestamp(row.expires_at),
+    })
+    .collect();
+
+    let history = sqlx::query!(
+        r#"
+        SELECT event, occurred_at
+        FROM subscription_events
+        WHERE subscription_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscription_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscription history.")?
+    .into_iter()
+    .map(|row| EventData {
+        event: row.event,
+        occurred_at: timestamp(row.occurred_at),
+    })
+    .collect();
+
+    let pending_deliveries = sqlx::query!(
+        r#"
+        SELECT q.newsletter_issue_id, i.title, q.n_retries, q.execute_after
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.subscriber_email = $1
+        ORDER BY q.execute_after
+        "#,
+        subscription.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the pending deliveries.")?
+    .into_iter()
+    .map(|row| PendingDeliveryData {
+        newsletter_issue_id: row.newsletter_issue_id,
+        title: row.title,
+        n_retries: row.n_retries,
+        next_attempt_at: timestamp(row.execute_after),
+    })
+    .collect();
+
+    let failed_deliveries = sqlx::query!(
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.n_retries, d.last_error, d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1
+        ORDER BY d.failed_at
+        "#,
+        subscription.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the failed deliveries.")?
+    .into_iter()
+    .map(|row| FailedDeliveryData {
+        newsletter_issue_id: row.newsletter_issue_id,
+        title: row.title,
+        n_retries: row.n_retries,
+        last_error: row.last_error,
+        failed_at: timestamp(row.failed_at),
+    })
+    .collect();
+
+    let data_requests = sqlx::query!(
+        r#"
+        SELECT kind, created_at, expires_at
+        FROM subscriber_data_requests
+        WHERE subscription_id = $1
+        ORDER BY created_at
+        "#,
+        subscription_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the data requests.")?
+    .into_iter()
+    .map(|row| DataRequestData {
+        kind: row.kind,
+        created_at: timestamp(row.created_at),
+        expires_at: timestamp(row.expires_at),
+    })
+    .collect();
+
+    Ok(SubscriberData {
+        subscription: SubscriptionData {
+            id: subscription.id,
+            email: subscription.email,
+            name: subscription.name,
+            status: subscription.status,
+            subscribed_at: timestamp(subscription.subscribed_at),
+            locale: subscription.locale,
+        },
+        confirmation_tokens,
+        history,
+        pending_deliveries,
+        failed_deliveries,
+        data_requests,
+    })
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::data_request_token::{
+    data_request_link, erased_email_hash, verify_data_request_token,
+};
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::mail::transport::EmailTransport;
+use crate::routes::subscriber_data::get::{
+    invalid_data_request_link, page, DataRequestKind, Parameters,
+};
+use crate::routes::subscriptions::delete_subscriber;
+use crate::utils::middleware::{e400, e500, see_other};
+
+/// How long a data export or erasure link stays valid.
+pub const DATA_REQUEST_TTL_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+    kind: String,
+}
+
+/// Answers the same way whether or not the address is subscribed, only its
+/// owner gets to find out, by receiving the email.
+#[tracing::instrument(
+    name = "Request subscriber data",
+    skip(form, pool, email_client, base_url, secret),
+    fields(kind = %form.kind)
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let DataRequestFormData { email, kind } = form.0;
+    let kind = DataRequestKind::try_from(kind).map_err(e400)?;
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(_) => {
+            FlashMessage::error("Please enter a valid email address.").send();
+            return Ok(see_other("/subscriptions/my-data"));
+        }
+    };
+
+    if let Some(subscription_id) = get_subscription_id(&pool, &email).await.map_err(e500)? {
+        let request_id = Uuid::new_v4();
+        insert_data_request(&pool, request_id, subscription_id, kind)
+            .await
+            .map_err(e500)?;
+        let link = data_request_link(&base_url.0, request_id, &secret);
+        let email_client = email_client.into_inner();
+        tokio::spawn(send_data_request_email(email_client, email, kind, link).in_current_span());
+    }
+
+    FlashMessage::info(
+        "If that address is on our list, we have emailed it a link to confirm the request.",
+    )
+    .send();
+    Ok(see_other("/subscriptions/my-data"))
+}
+
+/// Deletes the subscription and everything hanging off it, keeping only a
+/// keyed hash of the address in `erased_subscribers`.
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip(parameters, pool, secret),
+    fields(request_id = %parameters.request_id)
+)]
+pub async fn erase_subscriber_data(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !verify_data_request_token(&parameters.token, parameters.request_id, &secret) {
+        return Ok(invalid_data_request_link());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let (subscription_id, email) =
+        match get_pending_erasure(&mut transaction, parameters.request_id)
+            .await
+            .map_err(e500)?
+        {
+            Some(erasure) => erasure,
+            None => return Ok(invalid_data_request_link()),
+        };
+    delete_subscriber(&mut transaction, subscription_id)
+        .await
+        .context("Failed to delete the subscriber")
+        .map_err(e500)?;
+    insert_erased_subscriber(&mut transaction, &erased_email_hash(&email, &secret))
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page(
+            "<p>Your data has been erased, you will not receive any more emails from us.</p>",
+        )))
+}
+
+#[tracing::instrument(name = "Get subscription id by email", skip(pool, email))]
+async fn get_subscription_id(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscription.")?;
+    Ok(row.map(|row| row.id))
+}
+
+#[tracing::instrument(name = "Store subscriber data request", skip(pool))]
+async fn insert_data_request(
+    pool: &PgPool,
+    request_id: Uuid,
+    subscription_id: Uuid,
+    kind: DataRequestKind,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_data_requests (request_id, subscription_id, kind, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        request_id,
+        subscription_id,
+        kind.as_str(),
+        now,
+        now + Duration::hours(DATA_REQUEST_TTL_HOURS)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the subscriber data request.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send subscriber data request email", skip_all)]
+async fn send_data_request_email(
+    email_client: Arc<dyn EmailTransport>,
+    email: SubscriberEmail,
+    kind: DataRequestKind,
+    link: String,
+) {
+    let (subject, action) = match kind {
+        DataRequestKind::Export => (
+            "Your data",
+            "asked for a copy of the data we hold about this address",
+        ),
+        DataRequestKind::Erase => (
+            "Erase your data",
+            "asked us to erase the data we hold about this address",
+        ),
+    };
+    let result = email_client
+        .send_email(
+            &email,
+            subject,
+            &format!(
+                "Someone {}.<br />\
+                Click <a href=\"{}\">here</a> to confirm, the link is valid for {} hours.<br />\
+                If it was not you, you can ignore this email.",
+                action, link, DATA_REQUEST_TTL_HOURS
+            ),
+            &format!(
+                "Someone {}.\n\
+                Visit {} to confirm, the link is valid for {} hours.\n\
+                If it was not you, you can ignore this email.",
+                action, link, DATA_REQUEST_TTL_HOURS
+            ),
+        )
+        .await;
+    if let Err(e) = result {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a subscriber data request email"
+        );
+    }
+}
+
+#[tracing::instrument(name = "Get pending erasure", skip(transaction))]
+async fn get_pending_erasure(
+    transaction: &mut Transaction<'_, Postgres>,
+    request_id: Uuid,
+) -> Result<Option<(Uuid, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT subscriptions.id, subscriptions.email
+        FROM subscriber_data_requests
+        JOIN subscriptions ON subscriptions.id = subscriber_data_requests.subscription_id
+        WHERE
+            subscriber_data_requests.request_id = $1 AND
+            subscriber_data_requests.kind = 'erase' AND
+            subscriber_data_requests.expires_at > now()
+        FOR UPDATE OF subscriptions
+        "#,
+        request_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a pending erasure.")?;
+    Ok(row.map(|row| (row.id, row.email)))
+}
+
+/// Whether the owner of the address asked for their data to be erased. Only they
+/// may sign it up again, nothing done on their behalf (an import, a reminder
+/// sent by an admin) should bring it back.
+#[tracing::instrument(name = "Check for an erased subscriber", skip_all)]
+pub async fn is_erased_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_hash: &str,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email_hash FROM erased_subscribers WHERE email_hash = $1"#,
+        email_hash
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to check for an erased subscriber")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Store erased subscriber", skip_all)]
+async fn insert_erased_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_hash: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO erased_subscribers (email_hash, erased_at)
+        VALUES ($1, now())
+        ON CONFLICT (email_hash) DO NOTHING
+        "#,
+        email_hash
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store the erased subscriber.")?;
+    Ok(())
+}
@@ -1,66 +1,440 @@
-use actix_web::{web, HttpResponse, Responder};
-use sqlx::PgPool;
+use std::sync::Arc;
+
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use tracing;
+use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::telemetry::get_subscriber;
+use crate::config::{ConfirmationRedirects, SubscriptionSettings};
+use crate::domain::application::ApplicationBaseUrl;
+use crate::domain::new_subscriber::NewSubscriber;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_locale::SubscriberLocale;
+use crate::domain::subscriber_name::SubscriberName;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::routes::subscriptions::{
+    delete_tokens, generate_subscription_token, insert_token, send_confirmation_email,
+};
+use crate::subscription_history::{
+    count_confirmations_sent, record_subscription_event, SubscriptionEvent,
+};
+use crate::utils::middleware::{e500, see_other};
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+fn page(status: StatusCode, title: &str, body: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#,
+        ))
+}
+
+const RESEND_FORM: &str = r#"<form action="/subscriptions/confirm/resend" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <button type="submit">Send me a new link</button>
+    </form>"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfirmationOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    Invalid,
+    Expired,
+}
+
+impl ConfirmationOutcome {
+    fn redirect<'a>(&self, redirects: &'a ConfirmationRedirects) -> Option<&'a str> {
+        match self {
+            ConfirmationOutcome::Confirmed => redirects.confirmed.as_deref(),
+            ConfirmationOutcome::AlreadyConfirmed => redirects.already_confirmed.as_deref(),
+            ConfirmationOutcome::Invalid => redirects.invalid.as_deref(),
+            ConfirmationOutcome::Expired => redirects.expired.as_deref(),
+        }
+    }
+
+    fn into_response(self, redirects: &ConfirmationRedirects) -> HttpResponse {
+        if let Some(location) = self.redirect(redirects) {
+            return see_other(location);
+        }
+        match self {
+            ConfirmationOutcome::Confirmed => page(
+                StatusCode::OK,
+                "Subscription confirmed",
+                "<p>Thanks, your subscription is confirmed.</p>",
+            ),
+            ConfirmationOutcome::AlreadyConfirmed => page(
+                StatusCode::OK,
+                "Subscription confirmed",
+                "<p>Your subscription is already confirmed, there is nothing else to do.</p>",
+            ),
+            ConfirmationOutcome::Invalid => page(
+                StatusCode::UNAUTHORIZED,
+                "Confirm your subscription",
+                &format!(
+                    "<p>This confirmation link is not valid. \
+                    If you are still waiting to confirm your subscription, we can send you a new one.</p>\n    {}",
+                    RESEND_FORM
+                ),
+            ),
+            ConfirmationOutcome::Expired => page(
+                StatusCode::GONE,
+                "Confirm your subscription",
+                &format!(
+                    "<p>This confirmation link has expired, please request a new one.</p>\n    {}",
+                    RESEND_FORM
+                ),
+            ),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool, settings),
+    fields(outcome = tracing::field::Empty)
+)]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
-) -> impl Responder {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError(),
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let outcome = confirm_token(&pool, &parameters.subscription_token)
+        .await
+        .map_err(e500)?;
+    tracing::Span::current().record("outcome", &tracing::field::debug(outcome));
+    Ok(outcome.into_response(&settings.confirmation_redirects))
+}
+
+/// Tokens are single-use: a successful confirmation marks the token as consumed
+/// and deletes every other token the subscriber holds. Consumed and expired
+/// tokens are kept until the sweep, so following the link again is recognised.
+async fn confirm_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<ConfirmationOutcome, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+
+    let token = match get_token(&mut transaction, subscription_token).await? {
+        Some(token) => token,
+        None => return Ok(ConfirmationOutcome::Invalid),
+    };
+
+    if token.consumed_at.is_some() {
+        // Someone who unsubscribed since does not get to hear they are confirmed.
+        return Ok(if token.status == "confirmed" {
+            ConfirmationOutcome::AlreadyConfirmed
+        } else {
+            ConfirmationOutcome::Invalid
+        });
+    }
+
+    if token.expires_at <= Utc::now() {
+        record_subscription_event(
+            &mut transaction,
+            token.subscription_id,
+            SubscriptionEvent::ConfirmationLinkExpired,
+            None,
+        )
+        .await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record an expired link")?;
+        return Ok(ConfirmationOutcome::Expired);
+    }
+
+    // Only someone waiting for confirmation can be confirmed, not someone who was
+    // unsubscribed in the meantime.
+    if !confirm_subscriber(&mut transaction, token.subscription_id).await? {
+        return Ok(ConfirmationOutcome::Invalid);
+    }
+    consume_token(&mut transaction, token.subscription_id, subscription_token).await?;
+    record_subscription_event(
+        &mut transaction,
+        token.subscription_id,
+        SubscriptionEvent::Confirmed,
+        None,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
+    Ok(ConfirmationOutcome::Confirmed)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+/// Answers the same way whether or not the address is waiting for confirmation,
+/// and sends the email off the request so its latency does not give it away.
+#[tracing::instrument(
+    name = "Resend a confirmation link",
+    skip(form, pool, email_client, templates, base_url, settings)
+)]
+pub async fn resend_confirmation_link(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailTransport>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(_) => {
+            return Ok(page(
+                StatusCode::BAD_REQUEST,
+                "Confirm your subscription",
+                &format!(
+                    "<p>Please enter a valid email address.</p>\n    {}",
+                    RESEND_FORM
+                ),
+            ))
+        }
     };
 
-    match id {
-        None => HttpResponse::Unauthorized(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError();
-            }
-            HttpResponse::Ok()
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")
+        .map_err(e500)?;
+    let pending_subscriber = get_pending_subscriber_by_email(&mut transaction, email)
+        .await
+        .map_err(e500)?;
+    if let Some((subscriber_id, subscriber)) = pending_subscriber {
+        // Anyone can ask for a link to be sent to any address, do not let them
+        // flood an inbox with our emails.
+        let n_sent = count_confirmations_sent(
+            &mut transaction,
+            subscriber_id,
+            Utc::now() - chrono::Duration::hours(1),
+        )
+        .await
+        .map_err(e500)?;
+        if n_sent >= settings.max_confirmation_emails_per_hour {
+            tracing::warn!("Too many confirmation emails, ignoring the request for a new link");
+            return Ok(new_link_requested());
         }
+        delete_tokens(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to remove previous confirmation tokens")
+            .map_err(e500)?;
+        let subscription_token = generate_subscription_token();
+        insert_token(
+            &mut transaction,
+            subscriber_id,
+            &subscription_token,
+            settings.token_ttl_hours,
+        )
+        .await
+        .context("Failed to store a new confirmation token")
+        .map_err(e500)?;
+        record_subscription_event(
+            &mut transaction,
+            subscriber_id,
+            SubscriptionEvent::ConfirmationSent,
+            None,
+        )
+        .await
+        .map_err(e500)?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to resend a confirmation link")
+            .map_err(e500)?;
+        tokio::spawn(
+            send_new_confirmation_email(
+                email_client.into_inner(),
+                templates.into_inner(),
+                subscriber,
+                base_url.0.clone(),
+                subscription_token,
+            )
+            .in_current_span(),
+        );
     }
+
+    Ok(new_link_requested())
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool, subscriber_id))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id
+fn new_link_requested() -> HttpResponse {
+    page(
+        StatusCode::OK,
+        "Confirm your subscription",
+        "<p>If that address is waiting for confirmation, we have sent it a new link.</p>",
+    )
+}
+
+async fn send_new_confirmation_email(
+    email_client: Arc<dyn EmailTransport>,
+    templates: Arc<EmailTemplates>,
+    subscriber: NewSubscriber,
+    base_url: String,
+    subscription_token: String,
+) {
+    if let Err(e) = send_confirmation_email(
+        email_client.as_ref(),
+        &templates,
+        subscriber,
+        &base_url,
+        &subscription_token,
     )
-    .execute(pool)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
+    {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to resend a confirmation email"
+        );
+    }
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscriber_token))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+#[tracing::instrument(name = "Get pending subscriber by email", skip(transaction, email))]
+async fn get_pending_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: SubscriberEmail,
+) -> Result<Option<(Uuid, NewSubscriber)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, name, locale
+        FROM subscriptions
+        WHERE email = $1 AND status = 'pending_confirmation'
+        FOR UPDATE
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a pending subscriber.")?;
+
+    match row {
+        Some(row) => Ok(Some((
+            row.id,
+            NewSubscriber {
+                email,
+                name: SubscriberName::parse(row.name).map_err(anyhow::Error::msg)?,
+                locale: row
+                    .locale
+                    .map(SubscriberLocale::parse)
+                    .transpose()
+                    .map_err(anyhow::Error::msg)?,
+            },
+        ))),
+        None => Ok(None),
+    }
+}
+
+pub struct SubscriptionToken {
+    pub subscription_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    pub status: String,
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(transaction, subscriber_token))]
+pub async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscription_id FROM subscriptions_tokens WHERE subscription_token = $1"#,
+) -> Result<Option<SubscriptionToken>, anyhow::Error> {
+    // Lock the row so that two concurrent clicks cannot both consume it.
+    let token = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT
+            subscriptions_tokens.subscription_id,
+            subscriptions_tokens.expires_at,
+            subscriptions_tokens.consumed_at,
+            subscriptions.status
+        FROM subscriptions_tokens
+        JOIN subscriptions ON subscriptions.id = subscriptions_tokens.subscription_id
+        WHERE subscriptions_tokens.subscription_token = $1
+        FOR UPDATE OF subscriptions_tokens
+        "#,
         subscriber_token,
     )
-    .fetch_optional(pool)
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a subscription token")?;
+    Ok(token)
+}
+
+/// Returns `false` if the subscriber is not waiting for confirmation.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(transaction))]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .context("Failed to mark the subscriber as confirmed")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
 
-    Ok(result.map(|r| r.subscription_id))
+#[tracing::instrument(
+    name = "Consume subscription token",
+    skip(transaction, subscriber_token)
+)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscriber_token: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM subscriptions_tokens
+        WHERE subscription_id = $1 AND subscription_token != $2
+        "#,
+        subscriber_id,
+        subscriber_token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber's other tokens")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions_tokens
+        SET consumed_at = now()
+        WHERE subscription_token = $1
+        "#,
+        subscriber_token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to consume the subscription token")?;
+    Ok(())
 }
@@ -1,9 +1,6 @@
-use std::any::TypeId;
 use std::error::Error;
 use std::fmt::{Debug, Formatter};
 
-use actix_web::body::BoxBody;
-use actix_web::error::ParseError::Status;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, Responder, ResponseError};
 use anyhow::Context;
@@ -14,11 +11,17 @@ use sqlx::{PgPool, Postgres, Transaction};
 use tracing;
 use uuid::Uuid;
 
+use crate::config::SubscriptionSettings;
 use crate::domain::application::ApplicationBaseUrl;
 use crate::domain::new_subscriber::NewSubscriber;
 use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_locale::SubscriberLocale;
 use crate::domain::subscriber_name::SubscriberName;
-use crate::mail::send_email::EmailClient;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::subscription_history::{
+    count_confirmations_sent, record_subscription_event, SubscriptionEvent,
+};
 use crate::utils::error_helpers::error_chain_fmt;
 
 pub struct StoreTokenError(sqlx::Error);
@@ -74,6 +77,8 @@ impl ResponseError for SubscriberError {
 pub struct SubscriptionForm {
     name: String,
     email: String,
+    /// Language of the emails we send them, e.g. `fr`.
+    locale: Option<String>,
 }
 
 impl TryFrom<SubscriptionForm> for NewSubscriber {
@@ -82,13 +87,22 @@ impl TryFrom<SubscriptionForm> for NewSubscriber {
     fn try_from(form: SubscriptionForm) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(form.name)?;
         let email = SubscriberEmail::parse(form.email)?;
-        Ok(Self { email, name })
+        let locale = form
+            .locale
+            .filter(|locale| !locale.trim().is_empty())
+            .map(SubscriberLocale::parse)
+            .transpose()?;
+        Ok(Self {
+            email,
+            name,
+            locale,
+        })
     }
 }
 
 #[tracing::instrument(
 name = "Adding a new subscriber",
-skip(form, pool, email_client, domain),
+skip(form, pool, email_client, templates, domain, settings),
 fields(
 subscriber_email = % form.email,
 subscriber_name = % form.name,
@@ -97,8 +111,10 @@ subscriber_name = % form.name,
 pub async fn subscribe(
     form: web::Form<SubscriptionForm>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailTransport>,
+    templates: web::Data<EmailTemplates>,
     domain: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
 ) -> Result<impl Responder, SubscriberError> {
     let new_subscriber = form
         .0
@@ -110,15 +126,83 @@ pub async fn subscribe(
         .await
         .context("Failed to acquire a postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Failed to insert new subscriber into the database")?;
+        .context("Failed to insert new subscriber into the database")?
+    {
+        Some(subscriber_id) => {
+            record_subscription_event(
+                &mut transaction,
+                subscriber_id,
+                SubscriptionEvent::Subscribed,
+                None,
+            )
+            .await?;
+            subscriber_id
+        }
+        None => {
+            // Whatever state the existing subscription is in, the caller gets the
+            // same response: we do not want to leak who is on our mailing list.
+            let existing = get_existing_subscriber(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to retrieve an existing subscriber")?;
+            match existing.status.as_str() {
+                "confirmed" => {
+                    transaction
+                        .commit()
+                        .await
+                        .context("Failed to commit SQL transaction")?;
+                    return Ok(HttpResponse::Ok());
+                }
+                "unsubscribed" => {
+                    reactivate_subscriber(&mut transaction, existing.id, &new_subscriber)
+                        .await
+                        .context("Failed to reactivate an unsubscribed subscriber")?;
+                    record_subscription_event(
+                        &mut transaction,
+                        existing.id,
+                        SubscriptionEvent::Subscribed,
+                        None,
+                    )
+                    .await?;
+                }
+                // Signing up again is another way of asking for a new link.
+                _ => {
+                    let n_sent = count_confirmations_sent(
+                        &mut transaction,
+                        existing.id,
+                        Utc::now() - chrono::Duration::hours(1),
+                    )
+                    .await?;
+                    if n_sent >= settings.max_confirmation_emails_per_hour {
+                        return Ok(HttpResponse::Ok());
+                    }
+                }
+            }
+            delete_tokens(&mut transaction, existing.id)
+                .await
+                .context("Failed to remove previous confirmation tokens")?;
+            existing.id
+        }
+    };
 
     let subscription_token = generate_subscription_token();
 
-    insert_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("Failed to store confirmation token for a new subscriber")?;
+    insert_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        settings.token_ttl_hours,
+    )
+    .await
+    .context("Failed to store confirmation token for a new subscriber")?;
+    record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionEvent::ConfirmationSent,
+        None,
+    )
+    .await?;
 
     transaction
         .commit()
@@ -126,7 +210,8 @@ pub async fn subscribe(
         .context("Failed to commit SQL transaction to store a new subscriber")?;
 
     send_confirmation_email(
-        &email_client,
+        email_client.as_ref(),
+        &templates,
         new_subscriber,
         &domain.0,
         &subscription_token,
@@ -145,11 +230,19 @@ pub async fn insert_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscriber_token: &str,
+    ttl_hours: i64,
 ) -> Result<(), StoreTokenError> {
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::hours(ttl_hours);
     sqlx::query!(
-        r#"INSERT INTO subscriptions_tokens(subscription_token, subscription_id) VALUES ($1, $2)"#,
+        r#"
+        INSERT INTO subscriptions_tokens(subscription_token, subscription_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
         subscriber_token,
-        subscriber_id
+        subscriber_id,
+        created_at,
+        expires_at
     )
     .execute(transaction)
     .await
@@ -163,37 +256,36 @@ pub async fn insert_token(
 
 #[tracing::instrument(
     name = "Send confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, domain, token)
+    skip(email_client, templates, new_subscriber, domain, token)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+    email_client: &dyn EmailTransport,
+    templates: &EmailTemplates,
     new_subscriber: NewSubscriber,
     domain: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         domain, token,
     );
 
+    let mut context = tera::Context::new();
+    context.insert("name", new_subscriber.name.as_ref());
+    context.insert("confirmation_link", &confirmation_link);
+    let email = templates.render("confirmation", new_subscriber.locale.as_ref(), &context)?;
+
     email_client
         .send_email(
             &new_subscriber.email,
-            "Welcome!",
-            &format!(
-                "Welcome to our newsletter!<br />\
-                Click <a href=\"{}\">here</a> to confirm your subscription.
-                ",
-                confirmation_link,
-            ),
-            &format!(
-                "Welcome to our newsletter!\n Visit {} to confirm your subscriptions.",
-                confirmation_link,
-            ),
+            &email.subject,
+            &email.html,
+            &email.text,
         )
         .await
 }
 
+/// Returns `None` if a subscriber with the same email already exists.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(subscriber, transaction)
@@ -201,32 +293,133 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     // insert record into database.
     let subscription_id = Uuid::new_v4();
-    sqlx::query!(
+    let n_inserted_rows = sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1,$2,$3,$4,'pending_confirmation')
+    INSERT INTO subscriptions (id, email, name, subscribed_at, status, locale)
+    VALUES ($1,$2,$3,$4,'pending_confirmation',$5)
+    ON CONFLICT (email) DO NOTHING
     "#,
         subscription_id,
         subscriber.email.as_ref(),
         subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        subscriber.locale.as_ref().map(|locale| locale.as_ref())
     )
     .execute(transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows == 0 {
+        return Ok(None);
+    }
+    Ok(Some(subscription_id))
+}
+
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub status: String,
+}
+
+#[tracing::instrument(name = "Get existing subscriber", skip(transaction, email))]
+pub async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref(),
+    )
+    .fetch_one(transaction)
     .await
-    .map_err(|e| {
-        // tracing::error!("Failed to execute query: {:?}", e);
-        e
-        // ? will cause the error to be handled. Think of it like
-        // say raise/throw exception in python/java.
-    })?;
+}
 
-    Ok(subscription_id)
+#[tracing::instrument(
+    name = "Reactivate an unsubscribed subscriber",
+    skip(transaction, subscriber)
+)]
+pub async fn reactivate_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    // They go through the double opt-in again, as if they were new.
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            subscribed_at = $3,
+            status = 'pending_confirmation',
+            locale = $4
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        subscriber.name.as_ref(),
+        Utc::now(),
+        subscriber.locale.as_ref().map(|locale| locale.as_ref())
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Delete subscriber's tokens", skip(transaction))]
+pub async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE subscription_id = $1"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Removes the subscriber along with their tokens, history and pending or failed
+/// deliveries. Queued deliveries are keyed by email rather than by id, so they
+/// are cleaned up by hand. Returns `false` if there was no such subscriber.
+#[tracing::instrument(name = "Delete subscriber", skip(transaction))]
+pub async fn delete_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let email = match sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    {
+        Some(row) => row.email,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_dead_letters WHERE subscriber_email = $1"#,
+        email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Tokens, `subscription_events` and data requests go with it, their foreign keys cascade.
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *transaction)
+        .await?;
+    Ok(true)
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
+use crate::domain::new_subscriber::NewSubscriber;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_locale::SubscriberLocale;
+use crate::domain::subscriber_name::SubscriberName;
+use crate::domain::unsubscribe_token::verify_unsubscribe_token;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::subscription_history::{record_subscription_event, SubscriptionEvent};
+use crate::utils::middleware::e500;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Parameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {body}
+</body>
+</html>"#,
+    )
+}
+
+fn invalid_link() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .content_type(ContentType::html())
+        .body(page(
+            "Unsubscribe",
+            "<p>This unsubscribe link is not valid.</p>",
+        ))
+}
+
+/// Unsubscribing on a `GET` would let link scanners and prefetching mail clients
+/// unsubscribe people behind their back, so we only ask for a confirmation here.
+#[tracing::instrument(name = "Show unsubscribe form", skip(parameters, secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<Parameters>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    if !verify_unsubscribe_token(&parameters.token, parameters.subscriber_id, &secret) {
+        return invalid_link();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page(
+            "Unsubscribe",
+            &format!(
+                r#"<p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?subscriber_id={}&amp;token={}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>"#,
+                parameters.subscriber_id,
+                encode_minimal(&parameters.token),
+            ),
+        ))
+}
+
+/// Handles both our own form and RFC 8058 one-click requests, mail clients send the
+/// latter as a `POST` with a `List-Unsubscribe=One-Click` body we have no use for.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, secret, email_client, templates, base_url)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    email_client: web::Data<dyn EmailTransport>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !verify_unsubscribe_token(&parameters.token, parameters.subscriber_id, &secret) {
+        return Ok(invalid_link());
+    }
+
+    let unsubscribed = mark_subscriber_as_unsubscribed(&pool, parameters.subscriber_id, None)
+        .await
+        .map_err(e500)?;
+    // Only the first click gets an acknowledgement, mail clients may send several.
+    if unsubscribed {
+        if let Some(subscriber) = get_subscriber(&pool, parameters.subscriber_id)
+            .await
+            .map_err(e500)?
+        {
+            tokio::spawn(
+                send_unsubscribe_acknowledgement(
+                    email_client.into_inner(),
+                    templates.into_inner(),
+                    subscriber,
+                    base_url.0.clone(),
+                )
+                .in_current_span(),
+            );
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page(
+            "Unsubscribed",
+            "<p>You have been unsubscribed, you will not receive any more emails from us.</p>",
+        )))
+}
+
+/// `actor` is the admin unsubscribing someone, `None` when they did it themselves.
+/// Returns `false` if they were already unsubscribed.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    actor: Option<Uuid>,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed'
+        WHERE id = $1 AND status != 'unsubscribed'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the subscriber as unsubscribed.")?
+    .rows_affected();
+    // Clicking the link twice (or on a subscriber who is gone) is not an event.
+    if n_updated > 0 {
+        record_subscription_event(
+            &mut transaction,
+            subscriber_id,
+            SubscriptionEvent::Unsubscribed,
+            actor,
+        )
+        .await?;
+    }
+
+    // A confirmation link still sitting in their inbox must not subscribe them again.
+    sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE subscription_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove the tokens of an unsubscribed subscriber.")?;
+
+    // Drop whatever is still waiting to be delivered to them.
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE subscriber_email = (SELECT email FROM subscriptions WHERE id = $1)
+        "#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove pending deliveries for an unsubscribed subscriber.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+
+    Ok(n_updated > 0)
+}
+
+#[tracing::instrument(name = "Get unsubscribed subscriber", skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<NewSubscriber>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email, name, locale FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the unsubscribed subscriber.")?;
+
+    match row {
+        Some(row) => Ok(Some(NewSubscriber {
+            email: SubscriberEmail::parse(row.email).map_err(anyhow::Error::msg)?,
+            name: SubscriberName::parse(row.name).map_err(anyhow::Error::msg)?,
+            locale: row
+                .locale
+                .map(SubscriberLocale::parse)
+                .transpose()
+                .map_err(anyhow::Error::msg)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+#[tracing::instrument(name = "Send unsubscribe acknowledgement", skip_all)]
+async fn send_unsubscribe_acknowledgement(
+    email_client: Arc<dyn EmailTransport>,
+    templates: Arc<EmailTemplates>,
+    subscriber: NewSubscriber,
+    base_url: String,
+) {
+    let mut context = tera::Context::new();
+    context.insert("name", subscriber.name.as_ref());
+    context.insert("subscribe_url", &format!("{}/", base_url));
+    let result = match templates.render("unsubscribe_ack", subscriber.locale.as_ref(), &context) {
+        Ok(message) => {
+            email_client
+                .send_email(
+                    &subscriber.email,
+                    &message.subject,
+                    &message.html,
+                    &message.text,
+                )
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send an unsubscribe acknowledgement"
+        );
+    }
+}
@@ -1,72 +1,272 @@
 use std::net::TcpListener;
+use std::sync::Arc;
 
 use actix_session::storage::RedisSessionStore;
-use actix_session::SessionMiddleware;
+use actix_session::{CookieContentSecurity, SessionMiddleware};
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_lab::middleware::from_fn;
-use secrecy::{ExposeSecret, Secret};
+use secrecy::ExposeSecret;
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
-use crate::authentication::middleware::reject_anonymous_users;
-use crate::config::RedisConfig;
-use crate::domain::application::{ApplicationBaseUrl, HmacSecret};
-use crate::mail::send_email::EmailClient;
+use crate::authentication::csrf::reject_forged_requests;
+use crate::authentication::middleware::{reject_anonymous_users, require_editor, require_owner};
+use crate::authentication::session_registry::SessionRegistry;
+use crate::authentication::throttle::LoginThrottle;
+use crate::config::{
+    LoginThrottlingSettings, PasswordHashingSettings, RedisConfig, SubscriptionSettings,
+};
+use crate::domain::application::{ApplicationBaseUrl, HmacSecret, TotpEncryptionKey};
+use crate::mail::markdown::MarkdownRenderer;
+use crate::mail::templates::EmailTemplates;
+use crate::mail::transport::EmailTransport;
+use crate::routes::admin::api_tokens::get::api_tokens;
+use crate::routes::admin::api_tokens::post::{create_token, revoke_token};
 use crate::routes::admin::dashboard::admin_dashboard;
+use crate::routes::admin::deliveries::get::failed_deliveries;
+use crate::routes::admin::deliveries::post::requeue_failed_delivery;
+use crate::routes::admin::newsletters::get::publish_newsletter_form;
+use crate::routes::admin::newsletters::post::{
+    cancel_scheduled_issue, publish_newsletter_issue, reschedule_issue,
+};
+use crate::routes::admin::password::{get::change_password_form, post::change_password_endpoint};
+use crate::routes::admin::sessions::get::list_sessions;
+use crate::routes::admin::sessions::post::{revoke_session, sign_out_other_sessions};
+use crate::routes::admin::subscribers::export::export_subscribers;
+use crate::routes::admin::subscribers::get::{list_subscribers, subscriber_details};
+use crate::routes::admin::subscribers::import::{import_subscribers, import_subscribers_form};
+use crate::routes::admin::subscribers::post::{
+    delete_subscriber_endpoint, resend_confirmation, unsubscribe_subscriber,
+};
+use crate::routes::admin::two_factor::get::two_factor_settings;
+use crate::routes::admin::two_factor::post::{
+    disable_two_factor_endpoint, enable_two_factor_endpoint,
+};
+use crate::routes::admin::users::get::list_users;
+use crate::routes::admin::users::post::{disable_user, enable_user, invite_user};
 use crate::routes::health::health_check;
 use crate::routes::home::home;
+use crate::routes::invites::{get::accept_invite_form, post::accept_invite};
+use crate::routes::login::two_factor::{two_factor_form, verify_two_factor};
 use crate::routes::login::{get::login_form, post::login};
+use crate::routes::logout::logout::log_out;
 use crate::routes::newsletter::publish_newsletter;
-use crate::routes::subscription_confirm::confirm;
+use crate::routes::password_reset::get::{new_password_form, password_reset_form};
+use crate::routes::password_reset::post::{request_password_reset, reset_password};
+use crate::routes::subscriber_data::get::{confirm_data_request, data_request_form};
+use crate::routes::subscriber_data::post::{erase_subscriber_data, request_subscriber_data};
+use crate::routes::subscription_confirm::{confirm, resend_confirmation_link};
 use crate::routes::subscriptions::subscribe;
+use crate::routes::unsubscribe::{unsubscribe, unsubscribe_form};
+use crate::session_state::SESSION_COOKIE_NAME;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_connection: PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailTransport>,
+    email_templates: EmailTemplates,
+    markdown_renderer: MarkdownRenderer,
     redis_config: RedisConfig,
     domain: String,
     hmac_secret: HmacSecret,
+    subscription_settings: SubscriptionSettings,
+    totp_key: TotpEncryptionKey,
+    login_throttling: LoginThrottlingSettings,
+    password_hashing: PasswordHashingSettings,
 ) -> Result<Server, anyhow::Error> {
     let hmac_data = web::Data::new(HmacSecret(hmac_secret.0.clone()));
     let connection = web::Data::new(db_connection);
-    let email_client_data = web::Data::new(email_client);
+    let email_client_data: web::Data<dyn EmailTransport> = web::Data::from(email_client);
+    let email_templates = web::Data::new(email_templates);
+    let markdown_renderer = web::Data::new(markdown_renderer);
     let domain_url = web::Data::new(ApplicationBaseUrl(domain));
+    let subscription_settings = web::Data::new(subscription_settings);
+    let totp_key = web::Data::new(totp_key);
+    let password_hashing = web::Data::new(password_hashing);
     let message_key = Key::from(hmac_secret.0.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(message_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
     let redis_store = RedisSessionStore::new(redis_config.get_url()).await?;
+    let redis = redis::Client::open(redis_config.get_url())?
+        .get_tokio_connection_manager()
+        .await?;
+    let login_throttle = web::Data::new(LoginThrottle::new(
+        redis.clone(),
+        redis_config.key_prefix.clone(),
+        login_throttling,
+    ));
+    let session_registry = web::Data::new(SessionRegistry::new(
+        redis,
+        redis_store.clone(),
+        redis_config.key_prefix.clone(),
+    ));
+    let session_cookie_key = web::Data::new(message_key.clone());
 
     Ok(HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                message_key.clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(redis_store.clone(), message_key.clone())
+                    .cookie_name(SESSION_COOKIE_NAME.to_string())
+                    .cookie_content_security(CookieContentSecurity::Private)
+                    .build(),
+            )
             .wrap(TracingLogger::default())
             // get endpoints
             .route("/", web::get().to(home))
-            .route("/login", web::get().to(login_form))
             .route("/health", web::get().to(health_check))
+            .service(
+                web::resource("/login")
+                    .wrap(from_fn(reject_forged_requests))
+                    .route(web::get().to(login_form))
+                    .route(web::post().to(login)),
+            )
+            .service(
+                web::resource("/login/2fa")
+                    .wrap(from_fn(reject_forged_requests))
+                    .route(web::get().to(two_factor_form))
+                    .route(web::post().to(verify_two_factor)),
+            )
             // post endpoints
-            .route("/login", web::post().to(login))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/newsletters", web::post().to(publish_newsletter))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation_link),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/subscriptions/my-data", web::get().to(data_request_form))
+            .route(
+                "/subscriptions/my-data",
+                web::post().to(request_subscriber_data),
+            )
+            .route(
+                "/subscriptions/my-data/confirm",
+                web::get().to(confirm_data_request),
+            )
+            .route(
+                "/subscriptions/my-data/confirm",
+                web::post().to(erase_subscriber_data),
+            )
+            .route("/invites/accept", web::get().to(accept_invite_form))
+            .route("/invites/accept", web::post().to(accept_invite))
+            .route("/password-reset", web::get().to(password_reset_form))
+            .route("/password-reset", web::post().to(request_password_reset))
+            .route("/password-reset/confirm", web::get().to(new_password_form))
+            .route("/password-reset/confirm", web::post().to(reset_password))
             .service(
                 web::scope("/admin")
+                    // Wrapped first so that it runs after the session check.
+                    .wrap(from_fn(reject_forged_requests))
                     .wrap(from_fn(reject_anonymous_users))
-                    .route("/dashboard", web::get().to(admin_dashboard)),
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password_endpoint))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/sessions", web::get().to(list_sessions))
+                    .route("/sessions/revoke", web::post().to(revoke_session))
+                    .route(
+                        "/sessions/sign-out-others",
+                        web::post().to(sign_out_other_sessions),
+                    )
+                    .route("/2fa", web::get().to(two_factor_settings))
+                    .route("/2fa/enable", web::post().to(enable_two_factor_endpoint))
+                    .route("/2fa/disable", web::post().to(disable_two_factor_endpoint))
+                    .route("/deliveries/failed", web::get().to(failed_deliveries))
+                    .service(
+                        web::resource("/deliveries/failed/requeue")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(requeue_failed_delivery)),
+                    )
+                    .service(
+                        web::resource("/newsletters")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(publish_newsletter_form))
+                            .route(web::post().to(publish_newsletter_issue)),
+                    )
+                    .service(
+                        web::resource("/newsletters/scheduled/cancel")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(cancel_scheduled_issue)),
+                    )
+                    .service(
+                        web::resource("/newsletters/scheduled/reschedule")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(reschedule_issue)),
+                    )
+                    .service(
+                        web::resource("/api-tokens")
+                            .wrap(from_fn(require_editor))
+                            .route(web::get().to(api_tokens))
+                            .route(web::post().to(create_token)),
+                    )
+                    .service(
+                        web::resource("/api-tokens/revoke")
+                            .wrap(from_fn(require_editor))
+                            .route(web::post().to(revoke_token)),
+                    )
+                    .service(
+                        web::scope("/subscribers")
+                            .route("", web::get().to(list_subscribers))
+                            .service(
+                                web::resource("/resend-confirmation")
+                                    .wrap(from_fn(require_editor))
+                                    .route(web::post().to(resend_confirmation)),
+                            )
+                            .service(
+                                web::resource("/unsubscribe")
+                                    .wrap(from_fn(require_editor))
+                                    .route(web::post().to(unsubscribe_subscriber)),
+                            )
+                            .service(
+                                web::resource("/delete")
+                                    .wrap(from_fn(require_owner))
+                                    .route(web::post().to(delete_subscriber_endpoint)),
+                            )
+                            .service(
+                                web::resource("/import")
+                                    .wrap(from_fn(require_editor))
+                                    .route(web::get().to(import_subscribers_form))
+                                    .route(web::post().to(import_subscribers)),
+                            )
+                            .service(
+                                web::resource("/export")
+                                    .wrap(from_fn(require_editor))
+                                    .route(web::get().to(export_subscribers)),
+                            )
+                            .route("/{subscriber_id}", web::get().to(subscriber_details)),
+                    )
+                    .service(
+                        web::scope("/users")
+                            .wrap(from_fn(require_owner))
+                            .route("", web::get().to(list_users))
+                            .route("/invite", web::post().to(invite_user))
+                            .route("/disable", web::post().to(disable_user))
+                            .route("/enable", web::post().to(enable_user)),
+                    ),
             )
             .app_data(connection.clone())
             .app_data(email_client_data.clone())
+            .app_data(email_templates.clone())
+            .app_data(markdown_renderer.clone())
             .app_data(domain_url.clone())
             .app_data(hmac_data.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(totp_key.clone())
+            .app_data(login_throttle.clone())
+            .app_data(session_registry.clone())
+            .app_data(session_cookie_key.clone())
+            .app_data(password_hashing.clone())
     })
     .listen(listener)?
     .run())
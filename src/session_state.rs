@@ -1,14 +1,31 @@
 use std::future::{ready, Ready};
 
 use actix_session::{Session, SessionExt};
+use actix_web::cookie::{Cookie, CookieJar, Key};
 use actix_web::dev::Payload;
 use actix_web::{FromRequest, HttpRequest};
+use chrono::Utc;
 use uuid::Uuid;
 
+/// Name of the cookie `SessionMiddleware` keeps the (encrypted) session key in.
+pub const SESSION_COOKIE_NAME: &str = "id";
+
 pub struct TypedSession(Session);
 
+/// A user who got their password right but still owes us a second factor.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PendingSecondFactor {
+    user_id: Uuid,
+    expires_at: i64,
+}
+
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_GENERATION_KEY: &'static str = "session_generation";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const PENDING_SECOND_FACTOR_KEY: &'static str = "pending_second_factor";
+    const PENDING_SECOND_FACTOR_TTL_SECS: i64 = 300;
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -22,11 +39,70 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn insert_session_generation(&self, generation: i32) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::SESSION_GENERATION_KEY, generation)
+    }
+
+    pub fn get_session_generation(&self) -> Result<Option<i32>, serde_json::Error> {
+        self.0.get(Self::SESSION_GENERATION_KEY)
+    }
+
+    /// Identifies the session in the `SessionRegistry`.
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// Marks the session as half-authenticated, it does not carry a user id so
+    /// `reject_anonymous_users` keeps treating it as anonymous.
+    pub fn insert_pending_second_factor(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(
+            Self::PENDING_SECOND_FACTOR_KEY,
+            PendingSecondFactor {
+                user_id,
+                expires_at: Utc::now().timestamp() + Self::PENDING_SECOND_FACTOR_TTL_SECS,
+            },
+        )
+    }
+
+    /// Returns `None` once the marker has expired.
+    pub fn get_pending_second_factor(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        let pending: Option<PendingSecondFactor> = self.0.get(Self::PENDING_SECOND_FACTOR_KEY)?;
+        Ok(pending
+            .filter(|p| p.expires_at > Utc::now().timestamp())
+            .map(|p| p.user_id))
+    }
+
+    pub fn remove_pending_second_factor(&self) {
+        self.0.remove(Self::PENDING_SECOND_FACTOR_KEY);
+    }
+
+    pub fn insert_csrf_token(&self, token: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
     pub fn log_out(self) {
         self.0.purge()
     }
 }
 
+/// Reads the key the session state is stored under in `RedisSessionStore` out of
+/// the session cookie, which `SessionMiddleware` encrypts with `key`.
+pub fn session_store_key(cookie: Option<Cookie<'static>>, key: &Key) -> Option<String> {
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie?);
+    jar.private(key)
+        .get(SESSION_COOKIE_NAME)
+        .map(|cookie| cookie.value().to_owned())
+}
+
 // We implement `FromRequest` an actix_web extractor which we can
 // implement on a type to allow it to be used as an actix web extractor.
 impl FromRequest for TypedSession {
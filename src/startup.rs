@@ -5,8 +5,9 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 
 use crate::config::{Configuration, DatabaseSettings};
-use crate::domain::application::HmacSecret;
-use crate::mail::send_email::EmailClient;
+use crate::domain::application::{HmacSecret, TotpEncryptionKey};
+use crate::mail::markdown::MarkdownRenderer;
+use crate::mail::templates::EmailTemplates;
 use crate::run::run;
 
 pub struct AppServer {
@@ -30,11 +31,11 @@ impl AppServer {
             listener.local_addr().unwrap()
         );
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("invalid sender email address.");
-        let email_client = EmailClient::new(configuration.email_client.clone(), sender_email);
+        let email_client = configuration.email_client.clone().transport()?;
+        let email_templates = EmailTemplates::load(&configuration.email_templates)?;
+        let markdown_renderer = MarkdownRenderer::load(&configuration.newsletters)?;
+        // Fail at startup rather than on the first login.
+        configuration.password_hashing.params()?;
 
         let address = configuration.app.host.clone();
         let port = listener.local_addr().unwrap().port();
@@ -42,9 +43,15 @@ impl AppServer {
             listener,
             db_connection,
             email_client,
+            email_templates,
+            markdown_renderer,
             configuration.redis,
             configuration.app.domain,
             HmacSecret(configuration.app.hmac_secret.clone()),
+            configuration.subscriptions,
+            TotpEncryptionKey(configuration.app.totp_encryption_key.clone()),
+            configuration.login_throttling,
+            configuration.password_hashing,
         )
         .await?;
 
@@ -0,0 +1,106 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Milestones of a subscription, shown to admins on the subscriber's page.
+#[derive(Debug, Clone, Copy)]
+pub enum SubscriptionEvent {
+    Subscribed,
+    Imported,
+    ConfirmationSent,
+    ConfirmationLinkExpired,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEvent::Subscribed => "subscribed",
+            SubscriptionEvent::Imported => "imported",
+            SubscriptionEvent::ConfirmationSent => "confirmation_sent",
+            SubscriptionEvent::ConfirmationLinkExpired => "confirmation_link_expired",
+            SubscriptionEvent::Confirmed => "confirmed",
+            SubscriptionEvent::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+/// `actor` is the admin who acted on the subscriber's behalf, `None` when the
+/// subscriber did it themselves.
+#[tracing::instrument(name = "Record subscription event", skip(transaction))]
+pub async fn record_subscription_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_id: Uuid,
+    event: SubscriptionEvent,
+    actor: Option<Uuid>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (id, subscription_id, event, actor_user_id)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        subscription_id,
+        event.as_str(),
+        actor
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to record a subscription event")?;
+    Ok(())
+}
+
+/// How many confirmation emails the subscriber was sent since `since`, whoever
+/// asked for them.
+#[tracing::instrument(name = "Count confirmation emails sent", skip(transaction))]
+pub async fn count_confirmations_sent(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_id: Uuid,
+    since: DateTime<Utc>,
+) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM subscription_events
+        WHERE subscription_id = $1 AND event = $2 AND occurred_at >= $3
+        "#,
+        subscription_id,
+        SubscriptionEvent::ConfirmationSent.as_str(),
+        since
+    )
+    .fetch_one(transaction)
+    .await
+    .context("Failed to count the confirmation emails sent")?;
+    Ok(row.count)
+}
+
+pub struct HistoryEntry {
+    pub event: String,
+    pub actor: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Most recent first.
+#[tracing::instrument(name = "Get subscription history", skip(pool))]
+pub async fn get_subscription_history(
+    pool: &PgPool,
+    subscription_id: Uuid,
+) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    let history = sqlx::query_as!(
+        HistoryEntry,
+        r#"
+        SELECT e.event, u.username as "actor?", e.occurred_at
+        FROM subscription_events e
+        LEFT JOIN users u ON u.user_id = e.actor_user_id
+        WHERE e.subscription_id = $1
+        ORDER BY e.occurred_at DESC
+        "#,
+        subscription_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the history of a subscription")?;
+    Ok(history)
+}
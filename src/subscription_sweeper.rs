@@ -0,0 +1,77 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::config::{Configuration, SubscriptionSettings};
+use crate::startup::get_connection_pool;
+
+pub async fn run_sweeper_until_stopped(configuration: Configuration) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    sweeper_loop(connection_pool, configuration.subscriptions).await
+}
+
+async fn sweeper_loop(pool: PgPool, settings: SubscriptionSettings) -> Result<(), anyhow::Error> {
+    loop {
+        // A failed sweep is not worth crashing the process over, the next one will catch up.
+        let _ = sweep_stale_subscriptions(&pool, &settings).await;
+        tokio::time::sleep(Duration::from_secs(settings.sweep_interval_secs)).await;
+    }
+}
+
+/// Removes the subscribers who never confirmed within the retention window,
+/// together with their tokens, and the used or expired tokens kept around to
+/// recognise repeated clicks. The window starts over with every confirmation link we
+/// send, a subscriber who just asked for a new one keeps a working link.
+/// Returns how many subscribers were removed.
+#[tracing::instrument(skip_all, fields(n_removed = tracing::field::Empty), err)]
+pub async fn sweep_stale_subscriptions(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::hours(settings.pending_retention_hours);
+    let mut transaction = pool.begin().await?;
+    let stale_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE
+            status = 'pending_confirmation' AND
+            subscribed_at < $1 AND
+            NOT EXISTS (
+                SELECT 1 FROM subscriptions_tokens
+                WHERE
+                    subscription_id = subscriptions.id AND
+                    created_at >= $1
+            )
+        FOR UPDATE
+        "#,
+        cutoff
+    )
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+    sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE subscription_id = ANY($1)"#,
+        &stale_ids
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE consumed_at < $1 OR expires_at < $1"#,
+        cutoff
+    )
+    .execute(&mut transaction)
+    .await?;
+    let n_removed = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = ANY($1)"#,
+        &stale_ids
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    tracing::Span::current().record("n_removed", &n_removed);
+    Ok(n_removed)
+}
@@ -1,7 +1,3 @@
-use std::any::TypeId;
-use std::error::Error;
-use std::fmt::{Debug, Formatter};
-
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
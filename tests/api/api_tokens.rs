@@ -0,0 +1,112 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::api::newsletter::create_confirmed_subscriber;
+use crate::utils::helpers::{assert_is_redirect_to, spawn_app};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn basic_credentials_are_read_from_the_authorization_header() {
+    let app = spawn_app().await;
+
+    // `post_newsletters` relies on reqwest's `basic_auth`, i.e. a standard
+    // `Authorization: Basic ...` header.
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_can_be_published_with_an_api_token() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = app.create_api_token("CI").await;
+    let response = app
+        .post_newsletters_with_bearer_token(&newsletter_request_body(), &token)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<td>CI</td>"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn api_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let token = app.create_api_token("CI").await;
+
+    let saved = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash, token);
+}
+
+#[tokio::test]
+async fn unknown_api_tokens_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters_with_bearer_token(&newsletter_request_body(), "z2p_not-a-real-token")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn revoked_api_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI").await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    let response = app.post_revoke_api_token(token_id).await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The API token has been revoked.</i></p>"));
+
+    let response = app
+        .post_newsletters_with_bearer_token(&newsletter_request_body(), &token)
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_api_tokens() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/api-tokens", &app.addr))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
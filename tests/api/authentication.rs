@@ -6,7 +6,7 @@ async fn requests_missing_authentication_are_rejected() {
     let app = spawn_app().await;
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.addr))
+        .post(format!("{}/newsletters", &app.addr))
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
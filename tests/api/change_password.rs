@@ -0,0 +1,177 @@
+use uuid::Uuid;
+
+use crate::utils::helpers::{assert_is_redirect_to, build_api_client, spawn_app, with_csrf_token};
+
+const NEW_PASSWORD: &str = "a-perfectly-fine-new-password";
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form() {
+    let app = spawn_app().await;
+
+    let response = app.get_change_password().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": NEW_PASSWORD,
+            "new_password_check": NEW_PASSWORD,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": NEW_PASSWORD,
+            "new_password_check": "another-perfectly-fine-password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - \
+        the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": NEW_PASSWORD,
+            "new_password_check": NEW_PASSWORD,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn new_passwords_violating_the_policy_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let too_long = "a".repeat(129);
+    let test_cases = vec![
+        ("short", "at least 12 characters"),
+        (too_long.as_str(), "at most 128 characters"),
+        ("Password1234", "list of breached passwords"),
+    ];
+    for (new_password, error_message) in test_cases {
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": new_password,
+                "new_password_check": new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(error_message),
+            "The API did not reject the new password with `{}`.",
+            error_message
+        );
+    }
+
+    // The old password still works.
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": NEW_PASSWORD,
+            "new_password_check": NEW_PASSWORD,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": NEW_PASSWORD,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_every_other_session() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let other_client = build_api_client();
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    other_client
+        .post(format!("{}/login", &app.addr))
+        .form(&with_csrf_token(&other_client, &app.addr, &login_body).await)
+        .send()
+        .await
+        .unwrap();
+    let get_other_dashboard = || async {
+        other_client
+            .get(format!("{}/admin/dashboard", &app.addr))
+            .send()
+            .await
+            .unwrap()
+    };
+    assert_eq!(get_other_dashboard().await.status().as_u16(), 200);
+
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": NEW_PASSWORD,
+        "new_password_check": NEW_PASSWORD,
+    }))
+    .await;
+
+    assert_is_redirect_to(&get_other_dashboard().await, "/login");
+    // The session that changed the password is still valid.
+    assert_eq!(
+        app.get_admin_dashboard_response().await.status().as_u16(),
+        200
+    );
+}
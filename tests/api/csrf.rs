@@ -0,0 +1,115 @@
+use crate::utils::helpers::{
+    assert_is_redirect_to, build_api_client, get_csrf_token, spawn_app, TestApp,
+};
+
+fn login_body(app: &TestApp) -> serde_json::Value {
+    serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    })
+}
+
+#[tokio::test]
+async fn the_login_and_change_password_forms_carry_a_csrf_token() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<input type="hidden" name="csrf_token""#));
+
+    app.test_user.login(&app).await;
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(r#"<input type="hidden" name="csrf_token""#));
+}
+
+#[tokio::test]
+async fn login_without_a_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    // The session exists and holds a token, the form just does not send it.
+    app.get_login_html().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.addr))
+        .form(&login_body(&app))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_is_redirect_to(&app.get_admin_dashboard_response().await, "/login");
+}
+
+#[tokio::test]
+async fn login_with_the_csrf_token_of_another_session_is_rejected() {
+    let app = spawn_app().await;
+    let attacker_token = get_csrf_token(&build_api_client(), &app.addr).await;
+    app.get_login_html().await;
+
+    let mut body = login_body(&app);
+    body["csrf_token"] = attacker_token.into();
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.addr))
+        .form(&body)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn changing_password_without_a_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/password", &app.addr))
+        .form(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": "a-forged-new-password",
+            "new_password_check": "a-forged-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+    app.post_logout().await;
+    let response = app.post_login(&login_body(&app)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn logging_out_without_a_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.addr))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        app.get_admin_dashboard_response().await.status().as_u16(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn anonymous_admin_posts_are_redirected_to_login_first() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.addr))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,105 @@
+use uuid::Uuid;
+
+use crate::utils::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Publishes an issue and dead-letters its delivery to `subscriber_email`.
+async fn dead_lettered_delivery(app: &TestApp, subscriber_email: &str) -> Uuid {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as html</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let issue = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, 5, 'Connection refused', now())
+        "#,
+        issue.newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    issue.newsletter_issue_id
+}
+
+#[tokio::test]
+async fn requeued_deliveries_go_back_to_the_queue_with_a_fresh_retry_budget() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = dead_lettered_delivery(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_requeue_delivery(issue_id, "ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/deliveries/failed");
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains("The delivery to ursula_le_guin@gmail.com has been requeued."));
+
+    let task = sqlx::query!("SELECT subscriber_email, n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.pool)
+        .await
+        .expect("The delivery should have been requeued");
+    assert_eq!(task.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(task.n_retries, 0);
+    let dead_letters = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert!(dead_letters.is_empty());
+}
+
+#[tokio::test]
+async fn the_requeue_confirmation_does_not_render_the_submitted_email_as_html() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_email = "<script>alert('pwned')</script>@gmail.com";
+    let issue_id = dead_lettered_delivery(&app, subscriber_email).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_requeue_delivery(issue_id, subscriber_email).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/deliveries/failed");
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("&lt;script&gt;alert("));
+}
+
+#[tokio::test]
+async fn requeueing_an_unknown_delivery_is_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_requeue_delivery(Uuid::new_v4(), "ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/deliveries/failed");
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains("The delivery could not be found"));
+}
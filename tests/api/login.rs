@@ -1,8 +1,7 @@
 //! tests/api/helpers.rs
 
-use std::collections::HashSet;
-
-use reqwest::header::HeaderValue;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 
 use crate::utils::helpers::{assert_is_redirect_to, spawn_app};
 
@@ -34,11 +33,11 @@ async fn an_error_flash_message_is_set_on_failure() {
 
     // Act 2: follow redirect
     let html_page = app.get_login_html().await;
-    assert!(html_page.contains(r#"<p><i>Authentication failed<i></p>"#));
+    assert!(html_page.contains(r#"<p><i>Authentication failed</i></p>"#));
 
     // Act 3: ensure error message is removed
     let html_page = app.get_login_html().await;
-    assert!(!html_page.contains(r#"<p><i>Authentication failed<i></p>"#));
+    assert!(!html_page.contains(r#"<p><i>Authentication failed</i></p>"#));
 }
 
 #[tokio::test]
@@ -70,3 +69,71 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_admin_dashboard().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    let response = app.get_admin_dashboard_response().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_weak_password_hash_is_upgraded_on_login() {
+    // Arrange - store the password with cheaper parameters than configured
+    let app = spawn_app().await;
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let weak_password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .hash_password(app.test_user.password.as_bytes(), &salt)
+    .unwrap()
+    .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        weak_password_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    // Act
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert - the hash now uses the configured parameters and still works
+    let password_hash = sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .password_hash;
+    let hashing = &app.config.password_hashing;
+    assert!(password_hash.contains(&format!(
+        "m={},t={},p={}",
+        hashing.memory_cost_kib, hashing.time_cost, hashing.parallelism
+    )));
+
+    app.post_logout().await;
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,202 @@
+use uuid::Uuid;
+
+use crate::utils::helpers::{
+    assert_is_redirect_to, spawn_app, spawn_app_with_configuration, TestApp,
+};
+
+async fn fail_to_login(app: &TestApp, times: u32) {
+    for _ in 0..times {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": Uuid::new_v4().to_string(),
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body in plain text",
+            "html": "<p>Newsletter body in html</p>",
+        },
+    })
+}
+
+#[tokio::test]
+async fn a_wrong_password_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "definitely-not-the-password",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn the_account_is_locked_out_after_too_many_failed_logins() {
+    // Arrange
+    let app = spawn_app().await;
+    let max_failures = app.config.login_throttling.max_failures_per_username;
+    fail_to_login(&app, max_failures).await;
+
+    // Act - even the right password is refused while locked out
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many failed login attempts"));
+}
+
+#[tokio::test]
+async fn a_lockout_is_recorded_in_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+    let max_failures = app.config.login_throttling.max_failures_per_username;
+
+    // Act
+    fail_to_login(&app, max_failures).await;
+
+    // Assert
+    let entries = sqlx::query!(
+        "SELECT event, username, ip_address FROM auth_audit_log WHERE username = $1",
+        &app.test_user.username
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].event, "lockout");
+    assert_eq!(entries[0].ip_address.as_deref(), Some("127.0.0.1"));
+}
+
+async fn fail_to_login_from(app: &TestApp, forwarded_for: &str, times: u32) {
+    for _ in 0..times {
+        let body = app
+            .with_csrf_token(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": Uuid::new_v4().to_string(),
+            }))
+            .await;
+        let response = app
+            .api_client
+            .post(format!("{}/login", &app.addr))
+            .header("X-Forwarded-For", forwarded_for)
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+async fn audited_ip_address(app: &TestApp) -> Option<String> {
+    sqlx::query!(
+        "SELECT ip_address FROM auth_audit_log WHERE username = $1",
+        &app.test_user.username
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .ip_address
+}
+
+#[tokio::test]
+async fn forwarded_client_addresses_are_used_behind_a_trusted_proxy() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| c.login_throttling.trust_proxy_headers = true).await;
+    let max_failures = app.config.login_throttling.max_failures_per_username;
+
+    // Act
+    fail_to_login_from(&app, "203.0.113.7", max_failures).await;
+
+    // Assert
+    assert_eq!(
+        audited_ip_address(&app).await.as_deref(),
+        Some("203.0.113.7")
+    );
+}
+
+#[tokio::test]
+async fn forwarded_client_addresses_are_ignored_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    let max_failures = app.config.login_throttling.max_failures_per_username;
+
+    // Act
+    fail_to_login_from(&app, "203.0.113.7", max_failures).await;
+
+    // Assert
+    assert_eq!(audited_ip_address(&app).await.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_counter() {
+    // Arrange
+    let app = spawn_app().await;
+    let max_failures = app.config.login_throttling.max_failures_per_username;
+
+    // Act - stay just below the limit on both sides of a successful login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    fail_to_login(&app, max_failures - 1).await;
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+    fail_to_login(&app, max_failures - 1).await;
+
+    // Assert
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn basic_auth_returns_429_once_locked_out() {
+    // Arrange
+    let app = spawn_app().await;
+    let max_failures = app.config.login_throttling.max_failures_per_username;
+    for _ in 0..max_failures {
+        let response = app
+            .api_client
+            .post(format!("{}/newsletters", &app.addr))
+            .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+            .json(&newsletter_body())
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = app.post_newsletters(newsletter_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+    assert!(retry_after <= app.config.login_throttling.lockout_secs);
+}
@@ -1,6 +1,19 @@
+mod api_tokens;
 mod authentication;
+mod change_password;
+mod csrf;
+mod deliveries;
 mod health_check;
 mod login;
+mod login_throttling;
 mod newsletter;
+mod password_reset;
+mod sessions;
+mod subscriber_data;
+mod subscriber_import_export;
+mod subscribers;
 mod subscription;
 mod subscription_confirm;
+mod two_factor;
+mod unsubscribe;
+mod users;
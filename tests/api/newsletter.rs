@@ -1,14 +1,13 @@
 use std::time::Duration;
 
-use tokio::spawn;
-use tracing::enabled;
+use chrono::Utc;
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 use crate::utils::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+pub(crate) async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
     let _mock_guard = Mock::given(path("/email"))
@@ -32,10 +31,10 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
+pub(crate) async fn create_confirmed_subscriber(app: &TestApp) {
     let confirmation_links = create_unconfirmed_subscriber(app).await;
     reqwest::get(confirmation_links.html)
         .await
@@ -68,13 +67,14 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let response = app.post_newsletters(newsletter_request_body).await;
 
     assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app);
+    create_confirmed_subscriber(&app).await;
 
     Mock::given(path("/email"))
         .and(method("POST"))
@@ -94,6 +94,82 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // assert
     let response = app.post_newsletters(newsletter_request_body).await;
     assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_delivered_as_sanitized_html_and_plain_text() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "# Hello\n\nRead [the docs](https://example.com).<script>alert(1)</script>",
+        }
+    });
+
+    // act
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(html.contains("<h1>Newsletter title</h1>"));
+    assert!(html.contains("<h1>Hello</h1>"));
+    assert!(html.contains(r#"href="https://example.com""#));
+    assert!(!html.contains("<script"));
+    assert!(text.starts_with("Hello\n=====\n\nRead the docs (https://example.com)."));
+}
+
+#[tokio::test]
+async fn placeholders_are_filled_in_for_every_recipient() {
+    // arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "News for {{ name }}",
+        "content": {
+            "text": "Hi {{ name }}, leave at {{ unsubscribe_url }}",
+            "html": "<p>Hi {{name}}, subscribed since {{ confirmed_at }}</p>",
+        }
+    });
+
+    // act
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert_eq!(body["Subject"], "News for le guin");
+    assert!(text.starts_with("Hi le guin, leave at http"));
+    assert!(text.contains("/subscriptions/unsubscribe?"));
+    assert!(html.starts_with("<p>Hi le guin, subscribed since "));
+    assert!(!html.contains("{{"));
 }
 
 #[tokio::test]
@@ -116,6 +192,36 @@ async fn newsletters_returns_400_for_invalid_data() {
             }),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "html": "<p>Newsletter body in html</p>",
+                },
+            }),
+            "missing the plain text content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "text": "Newsletter body in plain text",
+                    "html": "<p>Newsletter body in html</p>",
+                    "markdown": "Newsletter body in *markdown*",
+                },
+            }),
+            "both markdown and explicit content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "text": "Hi {{ first_name }}",
+                    "html": "<p>Hi {{ first_name }}</p>",
+                },
+            }),
+            "an unknown placeholder",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -139,7 +245,7 @@ async fn non_existing_user_is_rejected() {
     let password = Uuid::new_v4().to_string();
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.addr))
+        .post(format!("{}/newsletters", &app.addr))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
                 "title": "Newsletter title",
@@ -159,6 +265,114 @@ async fn non_existing_user_is_rejected() {
     );
 }
 
+#[tokio::test]
+async fn newsletter_publishing_with_an_idempotency_key_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Publish the newsletter
+    let response = app
+        .post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Retry with the same key
+    let response = app
+        .post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_publishing_with_the_same_idempotency_key_sends_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act
+    let response1 =
+        app.post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    let app = spawn_app().await;
+
+    let response = app.get_publish_newsletter().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter() {
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_dashboard_links_to_the_newsletter_form() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_admin_dashboard().await;
+    assert!(html_page.contains(r#"<a href="/admin/newsletters">"#));
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(r#"name="idempotency_key""#));
+}
+
 #[tokio::test]
 async fn newsletter_creation_is_idempotent() {
     // Arrange
@@ -207,6 +421,38 @@ async fn newsletter_creation_is_idempotent() {
     // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
+#[tokio::test]
+async fn the_newsletter_form_rejects_unknown_placeholders() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ nickname }}",
+        "html_content": "<p>Hi {{ nickname }}</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>Only the {{ name }}, {{ unsubscribe_url }} and {{ confirmed_at }} \
+        placeholders can be used.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
+}
+
 #[tokio::test]
 async fn concurrent_form_submission_is_handled_gracefully() {
     // Arrange
@@ -242,3 +488,283 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have sent the newsletter email **once**
 }
+
+#[tokio::test]
+async fn failed_deliveries_are_rescheduled_with_a_backoff() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"is_delayed!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .expect("The failed delivery should still be queued");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.is_delayed);
+}
+
+#[tokio::test]
+async fn deliveries_exceeding_the_retry_budget_are_dead_lettered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Pretend every attempt but the last one already failed.
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET n_retries = $1",
+        app.config.delivery_worker.max_retries - 1
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, n_retries FROM issue_delivery_dead_letters")
+            .fetch_one(&app.pool)
+            .await
+            .expect("The delivery should have been dead-lettered");
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(
+        dead_letter.n_retries,
+        app.config.delivery_worker.max_retries
+    );
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_only_delivered_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let send_at = Utc::now() + chrono::Duration::hours(1);
+
+    // Act - Part 1 - Schedule the issue
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        },
+        "send_at": send_at.to_rfc3339(),
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // Act - Part 2 - Nothing goes out before it is due
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    // Act - Part 3 - It goes out once due
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() - interval '1 minute' \
+            WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let saved = sqlx::query!(
+        "SELECT status, published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "published");
+    assert!(saved.published_at.is_some());
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_an_invalid_send_at() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for send_at in ["2000-01-01T09:00:00+02:00", "2999-01-01T09:00:00", "monday"] {
+        let newsletter_request_body = serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as html</p>",
+            },
+            "send_at": send_at,
+        });
+
+        // Act
+        let response = app.post_newsletters(newsletter_request_body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject {} as a send_at.",
+            send_at
+        );
+    }
+}
+
+#[tokio::test]
+async fn newsletters_rejected_as_invalid_come_with_the_reason() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ first_name }}",
+            "html": "<p>Hi {{ first_name }}</p>",
+        },
+    });
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Unknown placeholder(s): {{ first_name }}"));
+}
+
+async fn schedule_from_the_admin_form(app: &TestApp) -> Uuid {
+    let send_at = Utc::now() + chrono::Duration::days(2);
+    let newsletter_request_body = serde_json::json!({
+        "title": "Monday issue",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "send_at": send_at.with_timezone(&chrono_tz::Europe::Paris).format("%Y-%m-%dT%H:%M").to_string(),
+        "timezone": "Europe/Paris",
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been scheduled.</i></p>"));
+    assert!(html_page.contains("Monday issue"));
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'scheduled'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_from_the_admin_form(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_cancel_scheduled_issue(issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("has been cancelled"));
+
+    // Assert
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let response = app
+        .post_reschedule_issue(issue_id, "2999-01-01T09:00", "UTC")
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("no longer scheduled"));
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_from_the_admin_form(&app).await;
+
+    // Act
+    let response = app
+        .post_reschedule_issue(issue_id, "2999-01-07T09:00", "UTC")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("has been rescheduled"));
+    assert!(html_page.contains("2999-01-07T09:00:00+00:00"));
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::utils::helpers::{assert_is_redirect_to, build_api_client, spawn_app, TestApp};
+
+const NEW_PASSWORD: &str = "a-perfectly-fine-new-password";
+const RESET_MESSAGE: &str =
+    "If that account exists, we have emailed it a link to reset its password.";
+
+async fn give_test_user_an_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        "ursula_le_guin@gmail.com",
+        app.test_user.user_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+}
+
+async fn post_password_reset(app: &TestApp, username: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/password-reset", &app.addr))
+        .form(&serde_json::json!({ "username": username }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_new_password(app: &TestApp, reset_link: Url, password: &str) -> reqwest::Response {
+    app.api_client
+        .post(reset_link)
+        .form(&serde_json::json!({
+            "new_password": password,
+            "new_password_check": password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// The reset email is sent in the background, give it a moment to arrive.
+async fn reset_link(app: &TestApp) -> Url {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if let Some(email_request) = requests.first() {
+            return app.get_confirmation_links(email_request).html;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No password reset email was sent.");
+}
+
+async fn request_reset_link(app: &TestApp) -> Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    give_test_user_an_email(app).await;
+
+    let response = post_password_reset(app, &app.test_user.username).await;
+    assert_is_redirect_to(&response, "/password-reset");
+    reset_link(app).await
+}
+
+async fn login_with(app: &TestApp, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": password,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn unknown_usernames_get_the_same_answer_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_password_reset(&app, "someone-who-does-not-exist").await;
+
+    assert_is_redirect_to(&response, "/password-reset");
+    let html_page = app
+        .api_client
+        .get(format!("{}/password-reset", &app.addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(RESET_MESSAGE));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[tokio::test]
+async fn known_usernames_get_the_same_answer_and_a_reset_link() {
+    let app = spawn_app().await;
+
+    request_reset_link(&app).await;
+
+    let html_page = app
+        .api_client
+        .get(format!("{}/password-reset", &app.addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(RESET_MESSAGE));
+}
+
+#[tokio::test]
+async fn a_reset_link_sets_a_new_password() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+
+    let response = app.api_client.get(reset_link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = post_new_password(&app, reset_link, NEW_PASSWORD).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset, you can now log in.</i></p>"));
+
+    let response = login_with(&app, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = login_with(&app, NEW_PASSWORD).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+
+    let response = post_new_password(&app, reset_link.clone(), NEW_PASSWORD).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = post_new_password(&app, reset_link, "yet-another-fine-password").await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = login_with(&app, NEW_PASSWORD).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_resets SET expires_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = post_new_password(&app, reset_link, NEW_PASSWORD).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    let response = login_with(&app, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_with_a_forged_token_is_rejected() {
+    let app = spawn_app().await;
+    let mut reset_link = request_reset_link(&app).await;
+    let reset_id = reset_link
+        .query_pairs()
+        .find(|(key, _)| key == "reset_id")
+        .unwrap()
+        .1
+        .into_owned();
+    reset_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("reset_id", &reset_id)
+        .append_pair("token", &"0".repeat(64));
+
+    let response = app.api_client.get(reset_link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = post_new_password(&app, reset_link, NEW_PASSWORD).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_existing_sessions() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let reset_link = request_reset_link(&app).await;
+
+    // Reset from another browser
+    let other_browser = build_api_client();
+    let response = other_browser
+        .post(reset_link)
+        .form(&serde_json::json!({
+            "new_password": NEW_PASSWORD,
+            "new_password_check": NEW_PASSWORD,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_admin_dashboard_response().await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,134 @@
+use crate::utils::helpers::{assert_is_redirect_to, spawn_app, with_csrf_token, TestApp};
+
+/// A cookie-aware client announcing itself with `user_agent`, like a browser would.
+fn browser(user_agent: &str) -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .user_agent(user_agent)
+        .build()
+        .unwrap()
+}
+
+async fn login(app: &TestApp, browser: &reqwest::Client) {
+    let response = browser
+        .post(format!("{}/login", &app.addr))
+        .form(
+            &with_csrf_token(
+                browser,
+                &app.addr,
+                &serde_json::json!({
+                    "username": &app.test_user.username,
+                    "password": &app.test_user.password,
+                }),
+            )
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+async fn get_sessions(app: &TestApp, browser: &reqwest::Client) -> reqwest::Response {
+    browser
+        .get(format!("{}/admin/sessions", &app.addr))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_sessions_html(app: &TestApp, browser: &reqwest::Client) -> String {
+    get_sessions(app, browser).await.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_your_sessions() {
+    let app = spawn_app().await;
+
+    let response = get_sessions(&app, &browser("laptop")).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn every_logged_in_browser_is_listed() {
+    let app = spawn_app().await;
+    let laptop = browser("laptop-browser");
+    let phone = browser("phone-browser");
+    login(&app, &laptop).await;
+    login(&app, &phone).await;
+
+    let html_page = get_sessions_html(&app, &laptop).await;
+
+    assert!(html_page.contains("laptop-browser"));
+    assert!(html_page.contains("phone-browser"));
+    assert!(html_page.contains("127.0.0.1"));
+    assert!(html_page.contains("This session"));
+}
+
+#[tokio::test]
+async fn signing_out_everywhere_else_logs_out_the_other_sessions() {
+    let app = spawn_app().await;
+    let laptop = browser("laptop-browser");
+    let phone = browser("phone-browser");
+    login(&app, &laptop).await;
+    login(&app, &phone).await;
+    // The phone sends its session cookie back at least once.
+    assert_eq!(get_sessions(&app, &phone).await.status().as_u16(), 200);
+
+    let response = laptop
+        .post(format!("{}/admin/sessions/sign-out-others", &app.addr))
+        .form(&with_csrf_token(&laptop, &app.addr, &serde_json::json!({})).await)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    let html_page = get_sessions_html(&app, &laptop).await;
+    assert!(html_page.contains("Signed out of 1 other session(s)."));
+    assert!(html_page.contains("laptop-browser"));
+    assert!(!html_page.contains("phone-browser"));
+
+    let response = get_sessions(&app, &phone).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_session_that_never_came_back_is_signed_out_too() {
+    let app = spawn_app().await;
+    let laptop = browser("laptop-browser");
+    let phone = browser("phone-browser");
+    login(&app, &laptop).await;
+    login(&app, &phone).await;
+
+    laptop
+        .post(format!("{}/admin/sessions/sign-out-others", &app.addr))
+        .form(&with_csrf_token(&laptop, &app.addr, &serde_json::json!({})).await)
+        .send()
+        .await
+        .unwrap();
+
+    let response = get_sessions(&app, &phone).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logging_out_removes_the_session_from_the_list() {
+    let app = spawn_app().await;
+    let laptop = browser("laptop-browser");
+    let phone = browser("phone-browser");
+    login(&app, &laptop).await;
+    login(&app, &phone).await;
+
+    let response = phone
+        .post(format!("{}/admin/logout", &app.addr))
+        .form(&with_csrf_token(&phone, &app.addr, &serde_json::json!({})).await)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = get_sessions_html(&app, &laptop).await;
+    assert!(!html_page.contains("phone-browser"));
+}
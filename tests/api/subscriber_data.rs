@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::api::newsletter::create_confirmed_subscriber;
+use crate::utils::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const REQUEST_MESSAGE: &str =
+    "If that address is on our list, we have emailed it a link to confirm the request.";
+
+async fn post_data_request(app: &TestApp, email: &str, kind: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions/my-data", &app.addr))
+        .form(&serde_json::json!({ "email": email, "kind": kind }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// The email is sent in the background, give it a moment to arrive.
+async fn data_request_link(app: &TestApp, n_previous_emails: usize) -> Url {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if let Some(email_request) = requests.get(n_previous_emails) {
+            return app.get_confirmation_links(email_request).html;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No data request email was sent.");
+}
+
+/// Signs up our subscriber and asks for a link on their behalf.
+async fn request_link(app: &TestApp, kind: &str) -> Url {
+    create_confirmed_subscriber(app).await;
+    let n_previous_emails = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_data_request(app, "ursula_le_guin@gmail.com", kind).await;
+    assert_is_redirect_to(&response, "/subscriptions/my-data");
+    data_request_link(app, n_previous_emails).await
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_data_request(&app, "nobody@example.com", "export").await;
+
+    assert_is_redirect_to(&response, "/subscriptions/my-data");
+    let html_page = app
+        .api_client
+        .get(format!("{}/subscriptions/my-data", &app.addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(REQUEST_MESSAGE));
+}
+
+#[tokio::test]
+async fn the_export_link_returns_everything_we_hold_about_the_subscriber() {
+    let app = spawn_app().await;
+    let link = request_link(&app, "export").await;
+    sqlx::query!("UPDATE subscriptions SET locale = 'fr'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .contains("my-data.json"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        data["subscription"]["email"].as_str(),
+        Some("ursula_le_guin@gmail.com")
+    );
+    assert_eq!(data["subscription"]["status"].as_str(), Some("confirmed"));
+    assert_eq!(data["subscription"]["locale"].as_str(), Some("fr"));
+    let events: Vec<_> = data["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect();
+    assert!(events.contains(&"confirmed"));
+    assert!(data["confirmation_tokens"].is_array());
+    assert!(data["pending_deliveries"].is_array());
+    assert!(data["failed_deliveries"].is_array());
+    assert_eq!(data["data_requests"][0]["kind"].as_str(), Some("export"));
+}
+
+#[tokio::test]
+async fn expired_links_are_rejected() {
+    let app = spawn_app().await;
+    let link = request_link(&app, "export").await;
+    sqlx::query!("UPDATE subscriber_data_requests SET expires_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn links_with_a_tampered_token_are_rejected() {
+    let app = spawn_app().await;
+    let mut link = request_link(&app, "export").await;
+    let request_id = link
+        .query_pairs()
+        .find(|(key, _)| key == "request_id")
+        .unwrap()
+        .1
+        .into_owned();
+    link.set_query(Some(&format!("request_id={}&token=deadbeef", request_id)));
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn following_an_erasure_link_does_not_erase_anything_by_itself() {
+    let app = spawn_app().await;
+    let link = request_link(&app, "erase").await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn confirming_an_erasure_leaves_only_a_hash_of_the_address() {
+    let app = spawn_app().await;
+    let link = request_link(&app, "erase").await;
+
+    let response = app.api_client.post(link.clone()).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+    let n_tokens = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+    let erased = sqlx::query!("SELECT email_hash FROM erased_subscribers")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(erased.len(), 1);
+    assert!(!erased[0].email_hash.contains("ursula"));
+    // The link cannot be used twice.
+    let response = app.api_client.post(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_export_link_cannot_be_used_to_erase() {
+    let app = spawn_app().await;
+    let link = request_link(&app, "export").await;
+
+    let response = app.api_client.post(link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let n_subscribers = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use reqwest::multipart::{Form, Part};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::application::HmacSecret;
+use zero2prod::domain::data_request_token::erased_email_hash;
+
+use crate::api::newsletter::create_confirmed_subscriber;
+use crate::utils::helpers::{get_csrf_token, spawn_app, TestApp, TestUser};
+
+async fn post_import(app: &TestApp, csv: &str, mode: &str) -> reqwest::Response {
+    let csrf_token = get_csrf_token(&app.api_client, &app.addr).await;
+    let form = Form::new()
+        .part(
+            "file",
+            Part::text(csv.to_owned())
+                .file_name("subscribers.csv")
+                .mime_str("text/csv")
+                .unwrap(),
+        )
+        .text("mode", mode.to_owned());
+    app.api_client
+        .post(format!(
+            "{}/admin/subscribers/import?csrf_token={}",
+            &app.addr, csrf_token
+        ))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_export(app: &TestApp, query: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/subscribers/export?{}", &app.addr, query))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_optional(&app.pool)
+        .await
+        .unwrap()
+        .map(|row| row.status)
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_and_invalid_ones_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\n\
+        ursula@example.com,Ursula\n\
+        not-an-email,Bob\n\
+        le.guin@example.com,Le Guin\n\
+        ursula@example.com,Ursula again\n";
+
+    let response = post_import(&app, csv, "confirmed").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 2 subscriber(s), skipped 0 already on the list."));
+    assert!(html_page.contains("Line 3: not-an-email is not a valid subscriber email."));
+    assert!(html_page.contains("Line 5: ursula@example.com already appears on line 2."));
+    assert_eq!(
+        subscriber_status(&app, "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+    assert_eq!(
+        subscriber_status(&app, "le.guin@example.com")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+    assert_eq!(subscriber_status(&app, "not-an-email").await, None);
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_skipped() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\nursula_le_guin@gmail.com,Someone else\nnew@example.com,New\n";
+
+    let response = post_import(&app, csv, "confirmed").await;
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 1 subscriber(s), skipped 1 already on the list."));
+    let name =
+        sqlx::query!("SELECT name FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .name;
+    assert_eq!(name, "le guin");
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_not_imported_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let secret = HmacSecret(app.config.app.hmac_secret.clone());
+    sqlx::query!(
+        "INSERT INTO erased_subscribers (email_hash, erased_at) VALUES ($1, now())",
+        erased_email_hash("ursula@example.com", &secret)
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = post_import(&app, "email,name\nUrsula@example.com,Ursula\n", "confirmed").await;
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 0 subscriber(s)"));
+    assert!(
+        html_page.contains("Skipped 1 address(es) whose owner asked for their data to be erased.")
+    );
+    assert_eq!(subscriber_status(&app, "Ursula@example.com").await, None);
+}
+
+#[tokio::test]
+async fn imported_subscribers_can_be_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nursula@example.com,Ursula\nle.guin@example.com,Le Guin\n";
+
+    let response = post_import(&app, csv, "send_confirmation").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        subscriber_status(&app, "ursula@example.com")
+            .await
+            .as_deref(),
+        Some("pending_confirmation")
+    );
+    // The emails go out in the background, give them a moment.
+    let mut email_requests = Vec::new();
+    for _ in 0..50 {
+        email_requests = app.email_server.received_requests().await.unwrap();
+        if email_requests.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(email_requests.len(), 2);
+    let confirmation_links = app.get_confirmation_links(&email_requests[0]);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_file_without_the_expected_columns_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_import(&app, "address\nursula@example.com\n", "confirmed").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn an_import_without_a_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let form = Form::new()
+        .text("file", "email,name\nursula@example.com,Ursula\n")
+        .text("mode", "confirmed");
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/subscribers/import", &app.addr))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(subscriber_status(&app, "ursula@example.com").await, None);
+}
+
+#[tokio::test]
+async fn viewers_cannot_import_or_export_subscribers() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    viewer.login(&app).await;
+
+    let response = post_import(&app, "email,name\nursula@example.com,Ursula\n", "confirmed").await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = get_export(&app, "").await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn export_streams_subscribers_filtered_by_status() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv =
+        "email,name\n\"ursula@example.com\",\"Le Guin, Ursula\"\nle.guin@example.com,Le Guin\n";
+    post_import(&app, csv, "confirmed").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'le.guin@example.com'"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = get_export(&app, "status=confirmed").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    let row = lines.next().unwrap();
+    assert!(row.starts_with("ursula@example.com,\"Le Guin, Ursula\",confirmed,"));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn export_rejects_unknown_statuses() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = get_export(&app, "status=bogus").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,408 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::application::HmacSecret;
+use zero2prod::domain::data_request_token::erased_email_hash;
+
+use crate::api::newsletter::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use crate::utils::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+async fn insert_subscriber(
+    app: &TestApp,
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        id,
+        email,
+        name,
+        subscribed_at,
+        status
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    id
+}
+
+async fn subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn get_html(app: &TestApp, path_and_query: &str) -> String {
+    app.api_client
+        .get(format!("{}{}", &app.addr, path_and_query))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn post_action(app: &TestApp, action: &str, subscriber_id: Uuid) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/subscribers/{}", &app.addr, action))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({ "subscriber_id": subscriber_id }))
+                .await,
+        )
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Reads the `after` cursor off the "Next page" link.
+fn next_page_link(html_page: &str) -> Option<String> {
+    let end = html_page.find(r#"">Next page"#)?;
+    let start = html_page[..end].rfind(r#"href=""#).unwrap() + r#"href=""#.len();
+    Some(html_page[start..end].replace("&amp;", "&"))
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.addr))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_searched() {
+    let app = spawn_app().await;
+    let now = Utc::now();
+    insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed", now).await;
+    insert_subscriber(&app, "octavia@example.com", "Octavia", "unsubscribed", now).await;
+    insert_subscriber(
+        &app,
+        "becky@example.com",
+        "Becky",
+        "pending_confirmation",
+        now,
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    let html_page = get_html(&app, "/admin/subscribers").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("octavia@example.com"));
+    assert!(html_page.contains("becky@example.com"));
+
+    let html_page = get_html(&app, "/admin/subscribers?status=confirmed").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(!html_page.contains("octavia@example.com"));
+    assert!(!html_page.contains("becky@example.com"));
+
+    let html_page = get_html(&app, "/admin/subscribers?q=OCTAV").await;
+    assert!(!html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("octavia@example.com"));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_signup_date() {
+    let app = spawn_app().await;
+    let old = Utc.ymd(2021, 3, 14).and_hms(12, 0, 0);
+    let recent = Utc.ymd(2022, 3, 14).and_hms(23, 59, 0);
+    insert_subscriber(&app, "old@example.com", "Old", "confirmed", old).await;
+    insert_subscriber(&app, "recent@example.com", "Recent", "confirmed", recent).await;
+    app.test_user.login(&app).await;
+
+    let html_page = get_html(
+        &app,
+        "/admin/subscribers?signed_up_from=2022-03-01&signed_up_to=2022-03-14",
+    )
+    .await;
+
+    assert!(!html_page.contains("old@example.com"));
+    assert!(html_page.contains("recent@example.com"));
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for query in [
+        "status=banned",
+        "signed_up_from=yesterday",
+        "after=nonsense",
+    ] {
+        let response = app
+            .api_client
+            .get(format!("{}/admin/subscribers?{}", &app.addr, query))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 400, "Query: {}", query);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_without_gaps_or_duplicates() {
+    let app = spawn_app().await;
+    // Several subscribers share a signup time, the id breaks the tie.
+    let start = Utc::now() - Duration::days(1);
+    for i in 0..60 {
+        let subscribed_at = start + Duration::minutes(i / 3);
+        let email = format!("subscriber{:02}@example.com", i);
+        insert_subscriber(&app, &email, "Someone", "confirmed", subscribed_at).await;
+    }
+    app.test_user.login(&app).await;
+
+    let first_page = get_html(&app, "/admin/subscribers?status=confirmed").await;
+    let next_link = next_page_link(&first_page).expect("There should be a second page");
+    assert!(next_link.contains("status=confirmed"));
+    let second_page = get_html(&app, &next_link).await;
+    assert!(next_page_link(&second_page).is_none());
+
+    let mut seen = Vec::new();
+    for i in 0..60 {
+        let email = format!("subscriber{:02}@example.com", i);
+        let on_first = first_page.contains(&email);
+        let on_second = second_page.contains(&email);
+        assert!(
+            on_first != on_second,
+            "{} should be on exactly one page",
+            email
+        );
+        seen.push(on_first);
+    }
+    assert_eq!(seen.iter().filter(|on_first| **on_first).count(), 50);
+    // Newest first: the latest signups are on the first page.
+    assert!(first_page.contains("subscriber59@example.com"));
+    assert!(second_page.contains("subscriber00@example.com"));
+}
+
+#[tokio::test]
+async fn the_subscriber_page_shows_the_confirmation_history() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    let html_page = get_html(&app, &format!("/admin/subscribers/{}", id)).await;
+
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("<td>subscribed</td>"));
+    assert!(html_page.contains("<td>confirmation_sent</td>"));
+    assert!(html_page.contains("<td>confirmed</td>"));
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers/{}",
+            &app.addr,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn editors_can_resend_a_confirmation_email() {
+    let app = spawn_app().await;
+    let old_links = create_unconfirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.pool).await;
+    editor.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_action(&app, "resend-confirmation", id).await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    let html_page = get_html(&app, &format!("/admin/subscribers/{}", id)).await;
+    assert!(
+        html_page.contains("A new confirmation email has been sent to ursula_le_guin@gmail.com.")
+    );
+    assert!(html_page.contains(&editor.username));
+    // The previous link no longer works, the new one does.
+    let response = reqwest::get(old_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    let response = reqwest::get(new_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_not_sent_a_new_confirmation_email() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    let secret = HmacSecret(app.config.app.hmac_secret.clone());
+    sqlx::query!(
+        "INSERT INTO erased_subscribers (email_hash, erased_at) VALUES ($1, now())",
+        erased_email_hash("ursula_le_guin@gmail.com", &secret)
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_action(&app, "resend-confirmation", id).await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    let html_page = get_html(&app, &format!("/admin/subscribers/{}", id)).await;
+    assert!(html_page.contains("asked for their data to be erased"));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_a_new_confirmation_email() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_action(&app, "resend-confirmation", id).await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+}
+
+#[tokio::test]
+async fn editors_can_unsubscribe_a_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    let response = post_action(&app, "unsubscribe", id).await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    let saved = sqlx::query!("SELECT status FROM subscriptions WHERE id = $1", id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    let html_page = get_html(&app, &format!("/admin/subscribers/{}", id)).await;
+    assert!(html_page.contains("<td>unsubscribed</td>"));
+    assert!(html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn pending_subscribers_unsubscribed_by_an_admin_cannot_confirm_with_their_link() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+    let response = post_action(&app, "unsubscribe", id).await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions WHERE id = $1", id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn viewers_can_look_but_not_act() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    viewer.login(&app).await;
+
+    let html_page = get_html(&app, &format!("/admin/subscribers/{}", id)).await;
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(!html_page.contains("/admin/subscribers/unsubscribe"));
+
+    let response = post_action(&app, "unsubscribe", id).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn only_owners_can_delete_a_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.pool).await;
+    editor.login(&app).await;
+
+    let response = post_action(&app, "delete", id).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_them_and_their_history() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    let response = post_action(&app, "delete", id).await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = get_html(&app, "/admin/subscribers").await;
+    assert!(html_page.contains("The subscriber has been deleted."));
+    assert!(!html_page.contains("ursula_le_guin@gmail.com"));
+    let n_left = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM subscriptions_tokens WHERE subscription_id = $1) as "tokens!",
+            (SELECT count(*) FROM subscription_events WHERE subscription_id = $1) as "events!"
+        "#,
+        id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(n_left.tokens, 0);
+    assert_eq!(n_left.events, 0);
+}
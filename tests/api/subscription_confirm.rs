@@ -1,8 +1,25 @@
-use sqlx::{Connection, PgConnection};
+use std::time::Duration;
+
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::utils::helpers::spawn_app;
+use zero2prod::subscription_sweeper::sweep_stale_subscriptions;
+
+use crate::api::newsletter::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use crate::utils::helpers::{
+    assert_is_redirect_to, spawn_app, spawn_app_with_configuration, TestApp,
+};
+
+async fn n_events(app: &TestApp, event: &str) -> i64 {
+    sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM subscription_events WHERE event = $1"#,
+        event
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .count
+}
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -29,7 +46,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_link = app.get_confirmation_links(&email_request);
+    let confirmation_link = app.get_confirmation_links(email_request);
 
     reqwest::get(confirmation_link.html)
         .await
@@ -46,3 +63,346 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request);
+
+    let response = reqwest::get(confirmation_link.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Your subscription is already confirmed"));
+    assert_eq!(n_events(&app, "confirmed").await, 1);
+}
+
+#[tokio::test]
+async fn an_unknown_confirmation_link_shows_a_page_to_request_a_new_one() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-token",
+        app.addr
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link is not valid."));
+    assert!(html_page.contains(r#"action="/subscriptions/confirm/resend""#));
+}
+
+#[tokio::test]
+async fn a_used_link_is_not_valid_once_the_subscriber_has_unsubscribed() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_new_link_can_be_requested_from_the_expired_link_page() {
+    let app = spawn_app().await;
+    let old_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let html_page = reqwest::get(old_links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"action="/subscriptions/confirm/resend""#));
+    let n_previous_emails = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/confirm/resend", &app.addr))
+        .form(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("If that address is waiting for confirmation"));
+    // The email is sent in the background, give it a moment to arrive.
+    let mut email_request = None;
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.len() > n_previous_emails {
+            email_request = requests.into_iter().nth(n_previous_emails);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let new_links = app.get_confirmation_links(&email_request.expect("No email was sent."));
+    let response = reqwest::get(new_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_a_new_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/confirm/resend", &app.addr))
+        .form(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("If that address is waiting for confirmation"));
+}
+
+#[tokio::test]
+async fn new_links_are_rate_limited_per_address() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // One more request than the limit leaves room for, counting the first email.
+    let max_emails = app.config.subscriptions.max_confirmation_emails_per_hour;
+    for _ in 0..max_emails {
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions/confirm/resend", &app.addr))
+            .form(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+            .send()
+            .await
+            .unwrap();
+        // The answer does not tell whether an email went out.
+        assert_eq!(response.status().as_u16(), 200);
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.contains("If that address is waiting for confirmation"));
+    }
+    // Signing up again counts too.
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let n_sent =
+        sqlx::query!("SELECT id FROM subscription_events WHERE event = 'confirmation_sent'")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap()
+            .len();
+    assert_eq!(n_sent as i64, max_emails);
+}
+
+#[tokio::test]
+async fn outcomes_redirect_to_their_configured_url() {
+    let app = spawn_app_with_configuration(|c| {
+        c.subscriptions.confirmation_redirects.confirmed =
+            Some("https://example.com/welcome".into());
+        c.subscriptions.confirmation_redirects.invalid = Some("https://example.com/oops".into());
+    })
+    .await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    let response = app
+        .api_client
+        .get(confirmation_links.html.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "https://example.com/welcome");
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=not-a-token",
+            app.addr
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "https://example.com/oops");
+
+    // Outcomes without a URL still get our page.
+    let response = app
+        .api_client
+        .get(confirmation_links.html)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_a_410() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request);
+
+    sqlx::query!("UPDATE subscriptions_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_link.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired"));
+
+    // Still recognised as expired the second time around.
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn the_sweep_removes_stale_pending_subscribers() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=octavia&email=octavia_butler%40gmail.com".into())
+        .await;
+
+    // Only the first one is old enough to be swept.
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - interval '30 days' WHERE email = $1",
+        "ursula_le_guin@gmail.com"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions_tokens SET created_at = now() - interval '30 days'
+        WHERE subscription_id = (SELECT id FROM subscriptions WHERE email = $1)
+        "#,
+        "ursula_le_guin@gmail.com"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let n_removed = sweep_stale_subscriptions(&app.pool, &app.config.subscriptions)
+        .await
+        .unwrap();
+    assert_eq!(n_removed, 1);
+
+    let remaining = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].email, "octavia_butler@gmail.com");
+    let n_tokens = sqlx::query!("SELECT subscription_token FROM subscriptions_tokens")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .len();
+    assert_eq!(n_tokens, 1);
+}
+
+#[tokio::test]
+async fn the_sweep_keeps_subscribers_holding_a_fresh_link() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Signed up long ago, but the link was sent again just now.
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '30 days'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let n_removed = sweep_stale_subscriptions(&app.pool, &app.config.subscriptions)
+        .await
+        .unwrap();
+    assert_eq!(n_removed, 0);
+
+    let n_tokens = sqlx::query!("SELECT subscription_token FROM subscriptions_tokens")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .len();
+    assert_eq!(n_tokens, 1);
+}
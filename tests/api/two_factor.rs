@@ -0,0 +1,210 @@
+use chrono::Utc;
+use secrecy::Secret;
+
+use zero2prod::authentication::totp::{code_at_step, time_step};
+
+use crate::utils::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn extract_between<'a>(html: &'a str, start: &str, end: &str) -> &'a str {
+    let from = html
+        .find(start)
+        .expect("The page did not contain the marker")
+        + start.len();
+    let to = from + html[from..].find(end).unwrap();
+    &html[from..to]
+}
+
+fn code(secret: &str, step_offset: i64) -> String {
+    let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret).unwrap();
+    let step = time_step(Utc::now().timestamp()) + step_offset;
+    format!("{:06}", code_at_step(&Secret::new(secret), step))
+}
+
+async fn get_2fa_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/2fa", &app.addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn post_code(app: &TestApp, path: &str, code: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.addr, path))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({ "code": code }))
+                .await,
+        )
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Enrols the test user and returns the TOTP secret and the recovery codes.
+async fn enable_two_factor(app: &TestApp) -> (String, Vec<String>) {
+    app.test_user.login(app).await;
+    let html_page = get_2fa_html(app).await;
+    let secret = extract_between(&html_page, r#"<code id="totp-secret">"#, "</code>").to_string();
+
+    let response = post_code(app, "/admin/2fa/enable", &code(&secret, 0)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let codes = extract_between(&html_page, r#"<ul id="recovery-codes">"#, "</ul>")
+        .split("<code>")
+        .skip(1)
+        .map(|c| c.split("</code>").next().unwrap().to_string())
+        .collect();
+
+    app.post_logout().await;
+    (secret, codes)
+}
+
+async fn login_with_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn logging_in_with_two_factor_enabled_takes_a_second_step() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+
+    let response = login_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+
+    // Half-authenticated sessions are still anonymous.
+    let response = app.get_admin_dashboard_response().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // The enrolment used the current step, the next one is still within the window.
+    let response = post_code(&app, "/login/2fa", &code(&secret, 1)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard_response().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_and_replayed_codes_are_rejected() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+    login_with_password(&app).await;
+
+    let response = post_code(&app, "/login/2fa", "000000").await;
+    assert_is_redirect_to(&response, "/login/2fa");
+
+    // At or before the step used during enrolment, whether or not the clock has
+    // moved on to the next one since.
+    let response = post_code(&app, "/login/2fa", &code(&secret, -1)).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+
+    let response = app.get_admin_dashboard_response().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_drop_the_pending_login() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+    login_with_password(&app).await;
+
+    let max_failures = app.config.login_throttling.max_second_factor_failures;
+    for _ in 1..max_failures {
+        let response = post_code(&app, "/login/2fa", "000000").await;
+        assert_is_redirect_to(&response, "/login/2fa");
+    }
+    let response = post_code(&app, "/login/2fa", "000000").await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many invalid authentication codes"));
+
+    // Entering the password again does not buy more guesses, not even a right one.
+    let response = login_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+    let response = post_code(&app, "/login/2fa", &code(&secret, 1)).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard_response().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    login_with_password(&app).await;
+    let response = post_code(&app, "/login/2fa", &recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    login_with_password(&app).await;
+    let response = post_code(&app, "/login/2fa", &recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/2fa");
+}
+
+#[tokio::test]
+async fn totp_secrets_and_recovery_codes_are_not_stored_in_clear() {
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enable_two_factor(&app).await;
+
+    let saved = sqlx::query!(
+        "SELECT totp_secret_encrypted as \"totp_secret_encrypted!\" FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    let raw_secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret).unwrap();
+    assert!(!saved
+        .totp_secret_encrypted
+        .windows(raw_secret.len())
+        .any(|w| w == raw_secret.as_slice()));
+
+    let hashes = sqlx::query!("SELECT code_hash FROM user_recovery_codes")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert!(hashes
+        .iter()
+        .all(|h| !recovery_codes.contains(&h.code_hash)));
+}
+
+#[tokio::test]
+async fn the_second_step_requires_a_password_first() {
+    let app = spawn_app().await;
+
+    let response = post_code(&app, "/login/2fa", "123456").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn accounts_with_two_factor_enabled_cannot_publish_with_their_password() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI").await;
+    app.post_logout().await;
+    enable_two_factor(&app).await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    let response = app.post_newsletters(newsletter_request_body.clone()).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+
+    let response = app
+        .post_newsletters_with_bearer_token(&newsletter_request_body, &token)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
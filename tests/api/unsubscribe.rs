@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::api::newsletter::create_confirmed_subscriber;
+use crate::utils::helpers::{spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    })
+}
+
+/// Publishes an issue to our single confirmed subscriber and returns the
+/// unsubscribe link found in the `List-Unsubscribe` header of the email.
+async fn deliver_newsletter_and_get_unsubscribe_link(app: &TestApp) -> Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+
+    let list_unsubscribe_post = headers
+        .iter()
+        .find(|h| h["Name"] == "List-Unsubscribe-Post")
+        .unwrap();
+    assert_eq!(list_unsubscribe_post["Value"], "List-Unsubscribe=One-Click");
+
+    let list_unsubscribe = headers
+        .iter()
+        .find(|h| h["Name"] == "List-Unsubscribe")
+        .unwrap()["Value"]
+        .as_str()
+        .unwrap();
+    let raw_link = list_unsubscribe
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let mut link = Url::parse(raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+/// The acknowledgement is sent in the background, give it a moment to arrive.
+async fn wait_for_email(app: &TestApp, n_previous_emails: usize) -> serde_json::Value {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if let Some(email_request) = requests.get(n_previous_emails) {
+            return serde_json::from_slice(&email_request.body).unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No email was sent.");
+}
+
+async fn one_click_unsubscribe(link: Url) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_one_click_unsubscribe_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    assert_eq!(link.path(), "/subscriptions/unsubscribe");
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_does_not_unsubscribe_on_its_own() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_stops_further_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    // Act - Part 1 - One-click unsubscribe, as a mail client would do it
+    let n_previous_emails = app.email_server.received_requests().await.unwrap().len();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = one_click_unsubscribe(link).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    // They get one last email, acknowledging it.
+    let acknowledgement = wait_for_email(&app, n_previous_emails).await;
+    assert_eq!(acknowledgement["Subject"], "You have been unsubscribed");
+    drop(_mock_guard);
+
+    // Act - Part 2 - Publish another issue
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_with_an_invalid_token_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    let subscriber_id = link
+        .query_pairs()
+        .find(|(k, _)| k == "subscriber_id")
+        .unwrap()
+        .1
+        .to_string();
+    link.set_query(Some(&format!(
+        "subscriber_id={}&token={}",
+        subscriber_id,
+        Uuid::new_v4().to_simple()
+    )));
+
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_acknowledgement_is_sent_in_the_subscriber_locale_and_only_once() {
+    let app = spawn_app().await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    drop(_mock_guard);
+    let link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    let n_previous_emails = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    one_click_unsubscribe(link.clone()).await;
+    one_click_unsubscribe(link).await;
+
+    let acknowledgement = wait_for_email(&app, n_previous_emails).await;
+    assert_eq!(
+        acknowledgement["Subject"],
+        "Votre désinscription est prise en compte"
+    );
+}
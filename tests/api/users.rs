@@ -0,0 +1,212 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::utils::helpers::{
+    assert_is_redirect_to, build_api_client, spawn_app, with_csrf_token, TestUser,
+};
+
+const NEW_PASSWORD: &str = "a-perfectly-fine-new-password";
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.pool).await;
+    editor.login(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/users", &app.addr))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_newsletters() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    viewer.login(&app).await;
+
+    // The dashboard is fine...
+    let response = app.get_admin_dashboard_response().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // ...publishing is not, from the admin UI or the API.
+    let response = app.get_publish_newsletter().await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn an_invited_user_can_set_up_their_account_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/users/invite", &app.addr))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "email": "ursula_le_guin@gmail.com",
+                "role": "editor",
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let invite_link = app.get_confirmation_links(email_request).html;
+
+    // The invitee uses their own browser.
+    let invitee_client = build_api_client();
+    let response = invitee_client
+        .get(invite_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let account = serde_json::json!({
+        "username": "ursula",
+        "password": NEW_PASSWORD,
+        "password_check": NEW_PASSWORD,
+    });
+    let response = invitee_client
+        .post(invite_link.clone())
+        .form(&account)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let html_page = invitee_client
+        .get(format!("{}/login", &app.addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>Your account is ready, you can now log in.</i></p>"));
+
+    let saved = sqlx::query!("SELECT role, email FROM users WHERE username = 'ursula'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.role, "editor");
+    assert_eq!(saved.email.as_deref(), Some("ursula_le_guin@gmail.com"));
+
+    let login_body = serde_json::json!({
+        "username": "ursula",
+        "password": NEW_PASSWORD,
+    });
+    let response = invitee_client
+        .post(format!("{}/login", &app.addr))
+        .form(&with_csrf_token(&invitee_client, &app.addr, &login_body).await)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // The link cannot be used a second time.
+    let response = build_api_client()
+        .post(invite_link)
+        .form(&serde_json::json!({
+            "username": "someone-else",
+            "password": NEW_PASSWORD,
+            "password_check": NEW_PASSWORD,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invite_links_with_a_forged_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/invites/accept?invite_id={}&token=deadbeef",
+        &app.addr,
+        uuid::Uuid::new_v4()
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn disabling_an_account_logs_it_out_and_prevents_logging_back_in() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.pool).await;
+
+    let editor_client = build_api_client();
+    let login_body = serde_json::json!({
+        "username": &editor.username,
+        "password": &editor.password,
+    });
+    editor_client
+        .post(format!("{}/login", &app.addr))
+        .form(&with_csrf_token(&editor_client, &app.addr, &login_body).await)
+        .send()
+        .await
+        .unwrap();
+
+    app.test_user.login(&app).await;
+    let response = app
+        .api_client
+        .post(format!("{}/admin/users/disable", &app.addr))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({ "user_id": editor.user_id }))
+                .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let response = editor_client
+        .get(format!("{}/admin/dashboard", &app.addr))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = editor_client
+        .post(format!("{}/login", &app.addr))
+        .form(&with_csrf_token(&editor_client, &app.addr, &login_body).await)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
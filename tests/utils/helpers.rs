@@ -1,20 +1,22 @@
+use std::sync::Arc;
+
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use reqwest::Url;
-use sha3::Digest;
 use sqlx::postgres::PgQueryResult;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 
 use zero2prod::config::{get_configuration, Configuration, DatabaseSettings};
+use zero2prod::domain::application::{ApplicationBaseUrl, HmacSecret};
+use zero2prod::issue_delivery_worker::{release_due_issues, try_execute_task, ExecutionOutcome};
+use zero2prod::mail::transport::EmailTransport;
 use zero2prod::startup::{get_connection_pool, AppServer};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
-    let default_filter_level = "info".to_string();
-    let subscriber_name = "test".to_string();
     if std::env::var("TEST_LOG").is_ok() {
         init_subscriber(get_subscriber(
             "test".into(),
@@ -35,14 +37,20 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: String,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role: role.to_string(),
         }
     }
 
@@ -54,7 +62,7 @@ impl TestUser {
         .await;
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -66,10 +74,11 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
-            Uuid::new_v4(),
-            Uuid::new_v4().to_string(),
+            "INSERT INTO users (user_id, username, password_hash, role) VALUES ($1, $2, $3, $4)",
+            self.user_id,
+            &self.username,
             password_hash,
+            &self.role,
         )
         .execute(pool)
         .await
@@ -85,16 +94,25 @@ pub struct TestApp {
     pub pool: PgPool,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: Arc<dyn EmailTransport>,
 }
 
 impl TestApp {
+    /// `body` with the CSRF token of the session of `api_client` added to it.
+    pub async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        with_csrf_token(&self.api_client, &self.addr, body).await
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.addr))
-            .form(body)
+            .post(format!("{}/login", &self.addr))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -102,7 +120,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard_response(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.addr))
+            .get(format!("{}/admin/dashboard", &self.addr))
             .send()
             .await
             .expect("Failed to execute request")
@@ -110,7 +128,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> String {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.addr))
+            .get(format!("{}/admin/dashboard", &self.addr))
             .send()
             .await
             .expect("Failed to execute request")
@@ -121,7 +139,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.addr))
+            .get(format!("{}/login", &self.addr))
             .send()
             .await
             .expect("Failed to execute request")
@@ -132,7 +150,7 @@ impl TestApp {
 
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletters", &self.addr))
+            .post(format!("{}/newsletters", &self.addr))
             .json(&body)
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .send()
@@ -140,9 +158,24 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.addr))
+            .json(body)
+            .header("Idempotency-Key", idempotency_key)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.addr))
+            .post(format!("{}/subscriptions", &self.addr))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -168,15 +201,15 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.addr))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -188,7 +221,8 @@ impl TestApp {
 
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.addr))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -199,8 +233,8 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
-            .form(body)
+            .post(format!("{}/admin/password", &self.addr))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -208,7 +242,7 @@ impl TestApp {
 
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.addr))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -223,19 +257,158 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
-            .form(body)
+            .post(format!("{}/admin/newsletters", &self.addr))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_cancel_scheduled_issue(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/scheduled/cancel", &self.addr))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "newsletter_issue_id": issue_id }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reschedule_issue(
+        &self,
+        issue_id: Uuid,
+        send_at: &str,
+        timezone: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/scheduled/reschedule",
+                &self.addr
+            ))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({
+                        "newsletter_issue_id": issue_id,
+                        "send_at": send_at,
+                        "timezone": timezone,
+                    }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_requeue_delivery(
+        &self,
+        issue_id: Uuid,
+        subscriber_email: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/deliveries/failed/requeue", &self.addr))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({
+                        "newsletter_issue_id": issue_id,
+                        "subscriber_email": subscriber_email,
+                    }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_failed_deliveries_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/deliveries/failed", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api-tokens", &self.addr))
             .send()
             .await
             .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
     }
 
+    /// Creates a token through the admin UI and returns its plain-text value.
+    pub async fn create_api_token(&self, name: &str) -> String {
+        let html_page = self
+            .api_client
+            .post(format!("{}/admin/api-tokens", &self.addr))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "name": name }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap();
+        let start = html_page
+            .find(r#"<code id="api-token">"#)
+            .expect("The page did not contain a token")
+            + r#"<code id="api-token">"#.len();
+        let end = start + html_page[start..].find("</code>").unwrap();
+        html_page[start..end].to_string()
+    }
+
+    pub async fn post_revoke_api_token(&self, token_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api-tokens/revoke", &self.addr))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "token_id": token_id }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_bearer_token(
+        &self,
+        body: &serde_json::Value,
+        token: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.addr))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Does what the delivery worker would, including releasing the scheduled
+    /// issues that are due.
     pub async fn dispatch_all_pending_emails(&self) {
+        release_due_issues(&self.pool).await.unwrap();
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.pool,
+                self.email_client.as_ref(),
+                &self.config.delivery_worker,
+                &ApplicationBaseUrl(self.config.app.domain.clone()),
+                &HmacSecret(self.config.app.hmac_secret.clone()),
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
@@ -243,30 +416,83 @@ impl TestApp {
     }
 }
 
+/// A cookie-aware client that does not follow redirects, each one holds its own session.
+pub fn build_api_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+/// Reads the CSRF token of `client`'s session off the login page, which renders
+/// it for anonymous and logged-in sessions alike.
+pub async fn get_csrf_token(client: &reqwest::Client, addr: &str) -> String {
+    let html_page = client
+        .get(format!("{}/login", addr))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    let marker = r#"name="csrf_token" value=""#;
+    let start = html_page
+        .find(marker)
+        .expect("The page did not contain a CSRF token")
+        + marker.len();
+    let end = start + html_page[start..].find('"').unwrap();
+    html_page[start..end].to_string()
+}
+
+/// Adds the CSRF token of `client`'s session to a form body.
+pub async fn with_csrf_token<Body>(
+    client: &reqwest::Client,
+    addr: &str,
+    body: &Body,
+) -> serde_json::Value
+where
+    Body: serde::Serialize,
+{
+    let mut body = serde_json::to_value(body).unwrap();
+    body.as_object_mut()
+        .expect("Form bodies are objects")
+        .insert(
+            "csrf_token".into(),
+            get_csrf_token(client, addr).await.into(),
+        );
+    body
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with_configuration(|_| {}).await
+}
+
+/// Lets a test tweak the configuration before the app is built.
+pub async fn spawn_app_with_configuration(customise: impl FnOnce(&mut Configuration)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
     let email_server_url = email_server.uri();
 
-    let api_client = reqwest::Client::builder()
-        .redirect(reqwest::redirect::Policy::none())
-        .cookie_store(true)
-        .build()
-        .unwrap();
+    let api_client = build_api_client();
 
     let configuration = {
         let mut c = get_configuration().expect("should load configuration");
         let db_name = Uuid::new_v4().to_string();
 
-        c.email_client.base_url = email_server_url.into();
+        c.email_client.base_url = email_server_url;
+        // Test apps share one Redis, keep their keys apart.
+        c.redis.key_prefix = db_name.clone();
+        c.login_throttling.base_delay_ms = 10;
         c.database.database_name = db_name;
         c.app.port = 0;
+        customise(&mut c);
         c
     };
 
@@ -279,7 +505,7 @@ pub async fn spawn_app() -> TestApp {
 
     let application_port = server.port();
     let addr = format!("http://{}", server.to_server_address());
-    let _ = tokio::spawn(server.run_until_stopped());
+    tokio::spawn(server.run_until_stopped());
 
     let pool = get_connection_pool(&configuration.database);
 
@@ -287,6 +513,11 @@ pub async fn spawn_app() -> TestApp {
     test_user.store(&pool).await;
 
     TestApp {
+        email_client: configuration
+            .email_client
+            .clone()
+            .transport()
+            .expect("should have built the email transport"),
         pool,
         addr,
         test_user,
@@ -317,7 +548,7 @@ pub async fn configure_database(database_settings: &DatabaseSettings) -> PgPool
         .await
         .expect("Failed to migrate the database");
 
-    return db_pool;
+    db_pool
 }
 
 pub async fn drop_table(pool: &PgPool) -> sqlx::Result<PgQueryResult> {